@@ -14,17 +14,13 @@ fn main() {
     println!("Reading: {}",pathstr);
     match SMF::from_file(&Path::new(&pathstr[..])) {
         Ok(smf) => {
-            println!("format: {}",smf.format);
-            println!("tracks: {}",smf.tracks.len());
-            println!("division: {}",smf.division);
+            println!("{}",smf);
             let mut tnum = 1;
             for track in smf.tracks.iter() {
-                let mut time: u64 = 0;
                 println!("\n{}: {}\nevents:",tnum,track);
                 tnum+=1;
-                for event in track.events.iter() {
-                    println!("  {}",event.fmt_with_time_offset(time));
-                    time += event.vtime;
+                for (time,event) in track.iter_absolute() {
+                    println!("  time: {}\t{}",time,event.event);
                 }
             }
         }
@@ -1,8 +1,78 @@
 use std::cmp::Ordering;
 use std::collections::BinaryHeap;
-use std::ops::IndexMut;
+use std::error;
+use std::fmt;
+use std::mem;
+
+use ::{event_order,SMF,Event,SMFFormat,MetaEvent,MidiMessage,PositionMap,Track,TrackEvent};
+
+use meta::bpm_to_micros;
+
+/// An error that can occur when adding to an SMFBuilder
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum BuilderError {
+    /// There is no track at the given index
+    NoSuchTrack(usize),
+    /// Events can't be added to tracks added with `add_static_track`
+    StaticTrackNotMutable,
+    /// The track already has a copyright
+    CopyrightAlreadySet,
+    /// The track already has a name
+    NameAlreadySet,
+    /// A single track SMF can't be made from the given number of
+    /// tracks
+    NotSingleTrack(usize),
+    /// The division of the builder isn't a number of ticks per
+    /// quarter note
+    InvalidDivision(i16),
+    /// A time signature denominator that isn't a power of two
+    InvalidDenominator(u8),
+    /// A time signature with no beats to the bar
+    ZeroNumerator,
+    /// A step track needs at least one step per beat
+    NoStepsPerBeat,
+}
 
-use ::{SMF,Event,SMFFormat,MetaEvent,MidiMessage,Track,TrackEvent};
+impl error::Error for BuilderError {
+    fn description(&self) -> &str {
+        match *self {
+            BuilderError::NoSuchTrack(_) => "No track at the given index",
+            BuilderError::StaticTrackNotMutable => "Can't add events to static tracks",
+            BuilderError::CopyrightAlreadySet => "Track already has a copyright",
+            BuilderError::NameAlreadySet => "Track already has a name",
+            BuilderError::NotSingleTrack(_) => "Single track SMFs need exactly one track",
+            BuilderError::InvalidDivision(_) => "Division is not in ticks per quarter note",
+            BuilderError::InvalidDenominator(_) => "Time signature denominator is not a power of two",
+            BuilderError::ZeroNumerator => "Time signature numerator is 0",
+            BuilderError::NoStepsPerBeat => "Step track needs at least one step per beat",
+        }
+    }
+}
+
+impl fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuilderError::NoSuchTrack(track) => write!(f,"No track at index {}",track),
+            BuilderError::StaticTrackNotMutable => write!(f,"Can't add events to static tracks"),
+            BuilderError::CopyrightAlreadySet => write!(f,"Track already has a copyright"),
+            BuilderError::NameAlreadySet => write!(f,"Track already has a name"),
+            BuilderError::NotSingleTrack(tracks) => write!(f,"Single track SMFs need exactly one track, builder has {}",tracks),
+            BuilderError::InvalidDivision(division) => write!(f,"Division {} is not in ticks per quarter note",division),
+            BuilderError::InvalidDenominator(denominator) => write!(f,"Time signature denominator {} is not a power of two",denominator),
+            BuilderError::ZeroNumerator => write!(f,"Time signature numerator is 0"),
+            BuilderError::NoStepsPerBeat => write!(f,"Step track needs at least one step per beat"),
+        }
+    }
+}
+
+/// One step of a pattern for `SMFBuilder::add_step_track`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum StepCell {
+    /// Play a note for the length of the step
+    Note { note: u8, velocity: u8, channel: u8 },
+    /// Play nothing
+    Rest,
+}
 
 /// An AbsoluteEvent is an event that has an absolute time
 /// This is useful for apps that want to store events internally
@@ -48,9 +118,19 @@ impl AbsoluteEvent {
         &self.event
     }
 
+    /// Get mutable access to the event inside this AbsoluteEvent
+    pub fn get_event_mut(&mut self) -> &mut Event {
+        &mut self.event
+    }
+
     pub fn get_time(&self) -> u64 {
         self.time
     }
+
+    /// Set the absolute time of this event
+    pub fn set_time(&mut self, time: u64) {
+        self.time = time;
+    }
 }
 
 impl Eq for AbsoluteEvent {}
@@ -62,13 +142,10 @@ impl PartialEq for AbsoluteEvent {
                 (&Event::Midi(_),&Event::Meta(_)) => false,
                 (&Event::Meta(_),&Event::Midi(_)) => false,
                 (&Event::Meta(ref me),&Event::Meta(ref you)) => {
-                    me.command == you.command
-                },
-                (&Event::Midi(ref me),&Event::Midi(ref you)) => {
-                    me.data(0) == you.data(0)
-                        &&
-                    me.data(1) == me.data(1)
+                    me.command == you.command && me.raw_command() == you.raw_command()
                 },
+                // the same as comparing with `cmp`, by status and data bytes
+                (&Event::Midi(ref me),&Event::Midi(ref you)) => me == you,
             }
         } else {
             false
@@ -97,20 +174,10 @@ impl Ord for AbsoluteEvent {
                     (&Event::Meta(_),&Event::Midi(_)) => Ordering::Less,
                     (&Event::Meta(ref me),&Event::Meta(ref you)) => {
                         me.command.cmp(&you.command)
+                            .then(me.raw_command().cmp(&you.raw_command()))
                     },
-                    (&Event::Midi(ref me),&Event::Midi(ref you)) => {
-                        if      me.data(0) < you.data(0) { Ordering::Less }
-                        else if me.data(0) > you.data(0) { Ordering::Greater }
-                        else {
-                            if me.data(1) < you.data(1) {
-                                Ordering::Less
-                            } else if me.data(1) > you.data(1) {
-                                Ordering::Greater
-                            } else {
-                                res
-                            }
-                        }
-                    },
+                    // by status, then data bytes
+                    (&Event::Midi(ref me),&Event::Midi(ref you)) => me.cmp(you),
                 }
             }
         }
@@ -140,40 +207,82 @@ impl TrackBuilder {
         Track {
             copyright: self.copyright,
             name: self.name,
+            instrument: None,
             events: match self.events {
-                EventContainer::Heap(heap) => {
-                    let mut events = Vec::with_capacity(heap.len());
-                    let absevents = heap.into_sorted_vec();
-                    let mut prev_time = 0;
-                    for ev in absevents.into_iter() {
-                        let vtime =
-                            if prev_time == 0 {
-                                ev.time
-                            } else {
-                                ev.time - prev_time
-                            };
-                        prev_time = ev.time;
-                        events.push(TrackEvent {
-                            vtime: vtime,
-                            event: ev.event,
-                        });
-                    }
-                    events
-                },
+                EventContainer::Heap(heap) => TrackBuilder::heap_to_events(heap),
                 EventContainer::Static(vec) => vec,
             },
         }
     }
 
-    fn abs_time_from_delta(&self,delta: u64) -> u64 {
+    // Sort the events in `heap` and convert them to delta times
+    fn heap_to_events(heap: BinaryHeap<AbsoluteEvent>) -> Vec<TrackEvent> {
+        let mut events = Vec::with_capacity(heap.len());
+        let absevents = heap.into_sorted_vec();
+        let mut prev_time = 0;
+        for ev in absevents.into_iter() {
+            let vtime =
+                if prev_time == 0 {
+                    ev.time
+                } else {
+                    ev.time - prev_time
+                };
+            prev_time = ev.time;
+            events.push(TrackEvent {
+                vtime: vtime,
+                event: ev.event,
+            });
+        }
+        events
+    }
+
+    // Get the events of a static track, first turning the track into
+    // a static one if needed
+    fn static_mut(&mut self) -> &mut Vec<TrackEvent> {
+        if let EventContainer::Heap(ref mut heap) = self.events {
+            let heap = mem::take(heap);
+            self.events = EventContainer::Static(TrackBuilder::heap_to_events(heap));
+        }
+        match self.events {
+            EventContainer::Static(ref mut vec) => vec,
+            EventContainer::Heap(_) => unreachable!(),
+        }
+    }
+
+    // Add a meta event at the very start of the track
+    fn insert_meta_at_start(&mut self, event: MetaEvent) {
+        match self.events {
+            EventContainer::Heap(ref mut heap) => {
+                heap.push(AbsoluteEvent {
+                    time: 0,
+                    event: Event::Meta(event),
+                });
+            }
+            EventContainer::Static(ref mut vec) => {
+                vec.insert(0, TrackEvent {
+                    vtime: 0,
+                    event: Event::Meta(event),
+                });
+            }
+        }
+    }
+
+    fn heap_mut(&mut self) -> Result<&mut BinaryHeap<AbsoluteEvent>,BuilderError> {
+        match self.events {
+            EventContainer::Heap(ref mut heap) => Ok(heap),
+            EventContainer::Static(_) => Err(BuilderError::StaticTrackNotMutable),
+        }
+    }
+
+    fn abs_time_from_delta(&self,delta: u64) -> Result<u64,BuilderError> {
         match self.events {
             EventContainer::Heap(ref heap) => {
                 match heap.peek() {
-                    Some(e) => { e.time + delta }
-                    None => { delta }
+                    Some(e) => { Ok(e.time + delta) }
+                    None => { Ok(delta) }
                 }
             }
-            _ => { panic!("Can't call abs_time_from_delta on non-heap builder") }
+            EventContainer::Static(_) => Err(BuilderError::StaticTrackNotMutable),
         }
     }
 }
@@ -182,36 +291,52 @@ impl TrackBuilder {
 /// adding tracks to the builder via `add_track` and then adding
 /// events to each track.
 pub struct SMFBuilder {
-    tracks:Vec<TrackBuilder>
+    tracks:Vec<TrackBuilder>,
+    division: i16,
 }
 
 impl SMFBuilder {
-    /// Create a new SMFBuilder.  Initially the builder will have no tracks
+    /// Create a new SMFBuilder.  Initially the builder will have no
+    /// tracks and a division of 480 ticks per beat
     pub fn new() -> SMFBuilder {
         SMFBuilder {
             tracks: Vec::new(),
+            division: 480,
         }
     }
 
+    /// Set the division of the SMF that will be generated.  See
+    /// `SMF::division` for the meaning of the value.
+    pub fn set_division(&mut self, division: i16) {
+        self.division = division;
+    }
+
     /// Get the number of tracks currenly in the builder
     pub fn num_tracks(&self) -> usize {
         self.tracks.len()
     }
 
-    /// Add new a track to this builder
-    pub fn add_track(&mut self) {
+    /// Add new a track to this builder, returning the index of the
+    /// new track
+    pub fn add_track(&mut self) -> usize {
         self.tracks.push(TrackBuilder {
             copyright: None,
             name: None,
             events: EventContainer::Heap(BinaryHeap::new()),
         });
+        self.tracks.len() - 1
     }
 
-    /// Add a static track to the builder (note this will clone all events in the passed iterator)
+    /// Add a static track to the builder (note this will clone all
+    /// events in the passed iterator).  The events don't need to be
+    /// ordered, they are sorted by time, with meta events before midi
+    /// events at the same time.  Events that are otherwise equal keep
+    /// the order they were passed in.
     pub fn add_static_track<'a,I>(&mut self, track: I) where I: Iterator<Item=&'a AbsoluteEvent> {
+        let mut events: Vec<&AbsoluteEvent> = track.collect();
+        events.sort_by_key(|bev| event_order(bev.time,&bev.event));
         let mut cur_time: u64 = 0;
-        let vec = track.map(|bev| {
-            assert!(bev.time >= cur_time);
+        let vec = events.into_iter().map(|bev| {
             let vtime = bev.time - cur_time;
             cur_time = bev.time;
             TrackEvent {
@@ -226,122 +351,215 @@ impl SMFBuilder {
         });
     }
 
+    /// Add a new track played by a step sequencer, returning the
+    /// index of the new track.  Each cell of `pattern` is one step,
+    /// with `steps_per_beat` steps to a beat (e.g. 4 for sixteenth
+    /// notes), and each note lasts the whole step.  Step times are
+    /// worked out from the division of the builder, which must be in
+    /// ticks per beat.  Events can be added to the track afterwards,
+    /// like any other track from `add_track`.
+    ///
+    /// Fails if the division of the builder is in SMPTE frames, or if
+    /// `steps_per_beat` is 0.
+    pub fn add_step_track(&mut self, steps_per_beat: u32, pattern: &[StepCell]) -> Result<usize,BuilderError> {
+        if self.division <= 0 {
+            return Err(BuilderError::InvalidDivision(self.division));
+        }
+        if steps_per_beat == 0 {
+            return Err(BuilderError::NoStepsPerBeat);
+        }
+        let ticks_per_beat = self.division as u64;
+        // worked out from the start each time so rounding errors don't add up
+        let step_time = |step: usize| step as u64 * ticks_per_beat / steps_per_beat as u64;
+        let mut heap = BinaryHeap::new();
+        for (step,cell) in pattern.iter().enumerate() {
+            if let StepCell::Note { note, velocity, channel } = *cell {
+                heap.push(AbsoluteEvent::new_midi(step_time(step),MidiMessage::note_on(note,velocity,channel)));
+                heap.push(AbsoluteEvent::new_midi(step_time(step+1),MidiMessage::note_off(note,0,channel)));
+            }
+        }
+        self.tracks.push(TrackBuilder {
+            copyright: None,
+            name: None,
+            events: EventContainer::Heap(heap),
+        });
+        Ok(self.tracks.len() - 1)
+    }
+
+    fn track_mut(&mut self, track: usize) -> Result<&mut TrackBuilder,BuilderError> {
+        self.tracks.get_mut(track).ok_or(BuilderError::NoSuchTrack(track))
+    }
+
     /// Set the copyright for the track at index `track`.  This will
     /// also cause a copyright meta event to be inserted.
-    /// ## Panics
     ///
-    /// Panics if `track` is >= to the number of tracks in this
+    /// Fails if `track` is >= to the number of tracks in this
     /// builder, or if the track already has a copyright set.
-    pub fn set_copyright(&mut self, track: usize, copyright: String) {
-        assert!(self.tracks.len() > track);
-        assert!(self.tracks[track].copyright.is_none());
-        // let event = AbsoluteEvent {
-        //     time: 0,
-        //     event: Event::Meta(MetaEvent::copyright_notice(copyright.clone())),
-        // };
-        self.tracks[track].copyright = Some(copyright);
+    pub fn set_copyright(&mut self, track: usize, copyright: String) -> Result<(),BuilderError> {
+        let track = self.track_mut(track)?;
+        if track.copyright.is_some() {
+            return Err(BuilderError::CopyrightAlreadySet);
+        }
+        track.insert_meta_at_start(MetaEvent::copyright_notice(copyright.clone()));
+        track.copyright = Some(copyright);
+        Ok(())
     }
 
     /// Set the name for the track at index `track`.  This will
     /// also cause a name meta event to be inserted.
     ///
-    /// ## Panics
-    ///
-    /// Panics if `track` is >= to the number of tracks in this
+    /// Fails if `track` is >= to the number of tracks in this
     /// builder, or if the track already has a name set.
-    pub fn set_name(&mut self, track: usize, name: String) {
-        assert!(self.tracks.len() > track);
-        assert!(self.tracks[track].name.is_none());
-        // let event = AbsoluteEvent{
-        //     time: 0,
-        //     event: Event::Meta(MetaEvent::sequence_or_track_name(name.clone())),
-        // };
-        self.tracks[track].name = Some(name);
+    pub fn set_name(&mut self, track: usize, name: String) -> Result<(),BuilderError> {
+        let track = self.track_mut(track)?;
+        if track.name.is_some() {
+            return Err(BuilderError::NameAlreadySet);
+        }
+        track.insert_meta_at_start(MetaEvent::sequence_or_track_name(name.clone()));
+        track.name = Some(name);
+        Ok(())
     }
 
     /// Add a midi message to track at index `track` at absolute time
     /// `time`.
     ///
-    /// ## Panics
-    ///
-    /// Panics if `track` is >= to the number of tracks in this builder
-    pub fn add_midi_abs(&mut self, track: usize, time: u64, msg: MidiMessage) {
-        assert!(self.tracks.len() > track);
-        match self.tracks.index_mut(track).events {
-            EventContainer::Heap(ref mut heap) => {
-                heap.push(AbsoluteEvent {
-                    time: time,
-                    event: Event::Midi(msg),
-                });
-            }
-            _ => { panic!("Can't add events to static tracks") }
-        }
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn add_midi_abs(&mut self, track: usize, time: u64, msg: MidiMessage) -> Result<(),BuilderError> {
+        self.track_mut(track)?.heap_mut()?.push(AbsoluteEvent {
+            time: time,
+            event: Event::Midi(msg),
+        });
+        Ok(())
     }
 
     /// Add a midi message to track at index `track` at `delta` ticks
     /// after the last message (or at `delta` if no current messages
     /// exist)
     ///
-    /// ## Panics
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn add_midi_rel(&mut self, track: usize, delta: u64, msg: MidiMessage) -> Result<(),BuilderError> {
+        let time = self.track_mut(track)?.abs_time_from_delta(delta)?;
+        self.add_midi_abs(track,time,msg)
+    }
+
+    /// Add a note on message to track at index `track` at absolute
+    /// time `time`.  Returns the builder so calls can be chained.
     ///
-    /// Panics if `track` is >= to the number of tracks in this builder
-    pub fn add_midi_rel(&mut self, track: usize, delta: u64, msg: MidiMessage) {
-        assert!(self.tracks.len() > track);
-        let time = self.tracks[track].abs_time_from_delta(delta);
-        self.add_midi_abs(track,time,msg);
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn note_on_at(&mut self, track: usize, time: u64, note: u8, velocity: u8, channel: u8) -> Result<&mut SMFBuilder,BuilderError> {
+        self.add_midi_abs(track,time,MidiMessage::note_on(note,velocity,channel))?;
+        Ok(self)
+    }
+
+    /// Add a note off message to track at index `track` at absolute
+    /// time `time`.  Returns the builder so calls can be chained.
+    ///
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn note_off_at(&mut self, track: usize, time: u64, note: u8, velocity: u8, channel: u8) -> Result<&mut SMFBuilder,BuilderError> {
+        self.add_midi_abs(track,time,MidiMessage::note_off(note,velocity,channel))?;
+        Ok(self)
+    }
+
+    /// Add a control change message to track at index `track` at
+    /// absolute time `time`.  Returns the builder so calls can be
+    /// chained.
+    ///
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn control_change_at(&mut self, track: usize, time: u64, controller: u8, value: u8, channel: u8) -> Result<&mut SMFBuilder,BuilderError> {
+        self.add_midi_abs(track,time,MidiMessage::control_change(controller,value,channel))?;
+        Ok(self)
+    }
+
+    /// Add a program change message to track at index `track` at
+    /// absolute time `time`.  Returns the builder so calls can be
+    /// chained.
+    ///
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn program_change_at(&mut self, track: usize, time: u64, program: u8, channel: u8) -> Result<&mut SMFBuilder,BuilderError> {
+        self.add_midi_abs(track,time,MidiMessage::program_change(program,channel))?;
+        Ok(self)
+    }
+
+    /// Add a pitch bend message to track at index `track` at absolute
+    /// time `time`.  Returns the builder so calls can be chained.
+    ///
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn pitch_bend_at(&mut self, track: usize, time: u64, lsb: u8, msb: u8, channel: u8) -> Result<&mut SMFBuilder,BuilderError> {
+        self.add_midi_abs(track,time,MidiMessage::pitch_bend(lsb,msb,channel))?;
+        Ok(self)
+    }
+
+    /// Add a meta event to track at index `track` at absolute time
+    /// `time`.  Returns the builder so calls can be chained.
+    ///
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn meta_at(&mut self, track: usize, time: u64, event: MetaEvent) -> Result<&mut SMFBuilder,BuilderError> {
+        self.add_meta_abs(track,time,event)?;
+        Ok(self)
     }
 
     /// Add a meta event to track at index `track` at absolute  time
     /// `time`.
     ///
-    /// ## Panics
-    ///
-    /// Panics if `track` is >= to the number of tracks in this builder
-    pub fn add_meta_abs(&mut self, track: usize, time: u64, event: MetaEvent) {
-        assert!(self.tracks.len() > track);
-        match self.tracks.index_mut(track).events {
-            EventContainer::Heap(ref mut heap) => {
-                heap.push(AbsoluteEvent {
-                    time: time,
-                    event: Event::Meta(event),
-                });
-            }
-            _ => { panic!("Can't add events to static tracks") }
-        }
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn add_meta_abs(&mut self, track: usize, time: u64, event: MetaEvent) -> Result<(),BuilderError> {
+        self.track_mut(track)?.heap_mut()?.push(AbsoluteEvent {
+            time: time,
+            event: Event::Meta(event),
+        });
+        Ok(())
     }
 
     /// Add a meta event to track at index `track` at `delta` ticks
     /// after the last message (or at `delta` if no current messages
     /// exist)
     ///
-    /// ## Panics
-    ///
-    /// Panics if `track` is >= to the number of tracks in this builder
-    pub fn add_meta_rel(&mut self, track: usize, delta: u64, event: MetaEvent) {
-        assert!(self.tracks.len() > track);
-        let time = self.tracks[track].abs_time_from_delta(delta);
-        self.add_meta_abs(track,time,event);
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn add_meta_rel(&mut self, track: usize, delta: u64, event: MetaEvent) -> Result<(),BuilderError> {
+        let time = self.track_mut(track)?.abs_time_from_delta(delta)?;
+        self.add_meta_abs(track,time,event)
     }
 
     /// Add a TrackEvent to the track at index `track`.  The event
     /// will be added at `event.vtime` after the last event currently
     /// in the builder for the track.
     ///
-    /// ## Panics
-    ///
-    /// Panics if `track` is >= to the number of tracks in this builder
-    pub fn add_event(&mut self, track: usize, event: TrackEvent) {
-        assert!(self.tracks.len() > track);
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder, or if it is a static track
+    pub fn add_event(&mut self, track: usize, event: TrackEvent) -> Result<(),BuilderError> {
+        let track = self.track_mut(track)?;
         let bevent = AbsoluteEvent {
-            time: self.tracks[track].abs_time_from_delta(event.vtime),
+            time: track.abs_time_from_delta(event.vtime)?,
             event: event.event,
         };
-        match self.tracks.index_mut(track).events {
-            EventContainer::Heap(ref mut heap) => {
-                heap.push(bevent);
-            }
-            _ => { panic!("Can't add events to static tracks") }
-        }
+        track.heap_mut()?.push(bevent);
+        Ok(())
+    }
+
+    /// Append `events` to the end of the track at index `track`
+    /// exactly as given, keeping their delta times and order.  This
+    /// is useful when the events are already in order, like when
+    /// editing a track that was read from a file.
+    ///
+    /// The track becomes a static track, so the events already in it
+    /// are fixed in place and no more events can be added to it with
+    /// the other `add_*` methods.
+    ///
+    /// Fails if `track` is >= to the number of tracks in this
+    /// builder
+    pub fn add_events_raw(&mut self, track: usize, events: Vec<TrackEvent>) -> Result<(),BuilderError> {
+        self.track_mut(track)?.static_mut().extend(events);
+        Ok(())
     }
 
     /// Generate an SMF file with the events that have been added to
@@ -350,9 +568,123 @@ impl SMFBuilder {
         SMF {
             format: SMFFormat::MultiTrack,
             tracks: self.tracks.into_iter().map(|tb| tb.result()).collect(),
-            division: 0,
+            division: self.division,
         }
     }
+
+    /// Generate an SMF file of the given format with the events that
+    /// have been added to the builder.
+    ///
+    /// Fails if `format` is `SMFFormat::Single` and the builder
+    /// doesn't have exactly one track
+    pub fn result_as(self, format: SMFFormat) -> Result<SMF,BuilderError> {
+        if format == SMFFormat::Single && self.tracks.len() != 1 {
+            return Err(BuilderError::NotSingleTrack(self.tracks.len()));
+        }
+        let mut smf = self.result();
+        smf.format = format;
+        Ok(smf)
+    }
+}
+
+/// A ConductorTrackBuilder collects the tempo and time signature
+/// changes of a piece by bar and beat, and adds them to a track of an
+/// SMFBuilder (usually track 0) at the right times in ticks.  Bars
+/// and beats are counted from 1, and a beat is the note value of the
+/// denominator of the time signature in effect.  Until the first time
+/// signature change the time signature is 4/4.  The length of a beat
+/// in ticks comes from the division of the SMFBuilder.
+///
+/// ```
+/// use rimd::{ConductorTrackBuilder,SMFBuilder};
+///
+/// let mut builder = SMFBuilder::new();
+/// builder.set_division(480);
+/// builder.add_track();
+/// let mut conductor = ConductorTrackBuilder::new();
+/// conductor.tempo(1,1,120.0);
+/// conductor.time_signature(1,4,4).unwrap().time_signature(5,3,4).unwrap();
+/// conductor.add_to(&mut builder,0).unwrap();
+/// ```
+#[derive(Debug,Clone,Default)]
+pub struct ConductorTrackBuilder {
+    // (bar, numerator, denominator exponent), ordered by bar
+    time_signatures: Vec<(u32,u8,u8)>,
+    // (bar, beat, microseconds per quarter note)
+    tempos: Vec<(u32,u32,u32)>,
+}
+
+impl ConductorTrackBuilder {
+    /// Create a new ConductorTrackBuilder with no changes
+    pub fn new() -> ConductorTrackBuilder {
+        ConductorTrackBuilder {
+            time_signatures: Vec::new(),
+            tempos: Vec::new(),
+        }
+    }
+
+    /// Change the time signature to `numerator`/`denominator` at the
+    /// start of `bar`, replacing any other change at that bar.
+    ///
+    /// Fails if `numerator` is 0, or if `denominator` isn't a power
+    /// of two
+    pub fn time_signature(&mut self, bar: u32, numerator: u8, denominator: u8) -> Result<&mut ConductorTrackBuilder,BuilderError> {
+        if numerator == 0 {
+            return Err(BuilderError::ZeroNumerator);
+        }
+        if !denominator.is_power_of_two() {
+            return Err(BuilderError::InvalidDenominator(denominator));
+        }
+        let exponent = denominator.trailing_zeros() as u8;
+        match self.time_signatures.binary_search_by_key(&bar,|&(b,_,_)| b) {
+            Ok(i) => self.time_signatures[i] = (bar,numerator,exponent),
+            Err(i) => self.time_signatures.insert(i,(bar,numerator,exponent)),
+        }
+        Ok(self)
+    }
+
+    /// Change the tempo to `bpm` quarter notes per minute at `beat`
+    /// of `bar`
+    pub fn tempo(&mut self, bar: u32, beat: u32, bpm: f64) -> &mut ConductorTrackBuilder {
+        self.tempos.push((bar,beat,bpm_to_micros(bpm)));
+        self
+    }
+
+    /// Get the time in ticks of `beat` of `bar`, taking into account
+    /// the time signature changes before it, for an SMF with a
+    /// division of `ticks_per_quarter` ticks per quarter note
+    pub fn ticks_at(&self, ticks_per_quarter: u16, bar: u32, beat: u32) -> u64 {
+        self.position_map(ticks_per_quarter).bbt_to_tick(bar,beat)
+    }
+
+    // the time signature changes as a PositionMap, for an SMF with a
+    // division of `ticks_per_quarter` ticks per quarter note
+    fn position_map(&self, ticks_per_quarter: u16) -> PositionMap {
+        PositionMap::from_bars(&self.time_signatures,ticks_per_quarter)
+    }
+
+    /// Add the tempo and time signature changes to the track at index
+    /// `track` of `builder`.
+    ///
+    /// Fails if `track` is >= to the number of tracks in `builder`, if
+    /// it is a static track, or if the division of `builder` isn't in
+    /// ticks per quarter note
+    pub fn add_to(&self, builder: &mut SMFBuilder, track: usize) -> Result<(),BuilderError> {
+        if builder.division <= 0 {
+            return Err(BuilderError::InvalidDivision(builder.division));
+        }
+        let map = self.position_map(builder.division as u16);
+        for &(bar,numerator,exponent) in self.time_signatures.iter() {
+            // a midi clock is 1/24 of a quarter note, click once a beat
+            let clocks = ((96u32 >> exponent) as u8).max(1);
+            let event = MetaEvent::time_signature(numerator,exponent,clocks,8);
+            builder.add_meta_abs(track,map.bbt_to_tick(bar,1),event)?;
+        }
+        for &(bar,beat,micros) in self.tempos.iter() {
+            builder.add_meta_abs(track,map.bbt_to_tick(bar,beat),MetaEvent::tempo_setting(micros))?;
+        }
+        Ok(())
+    }
 }
 
 #[test]
@@ -364,7 +696,328 @@ fn simple_build() {
     let mut builder = SMFBuilder::new();
     builder.add_track();
 
-    builder.add_event(0, TrackEvent{vtime: 0, event: Event::Midi(note_on)});
-    builder.add_event(0, TrackEvent{vtime: 10, event: Event::Midi(note_off)});
+    builder.add_event(0, TrackEvent{vtime: 0, event: Event::Midi(note_on)}).unwrap();
+    builder.add_event(0, TrackEvent{vtime: 10, event: Event::Midi(note_off)}).unwrap();
     builder.result();
 }
+
+#[test]
+fn static_track_deltas() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(62,100,0)),
+        AbsoluteEvent::new_midi(25,MidiMessage::note_on(64,100,0)),
+    ];
+    let mut builder = SMFBuilder::new();
+    builder.add_static_track(events.iter());
+    let smf = builder.result();
+    let vtimes: Vec<u64> = smf.tracks[0].events.iter().map(|e| e.vtime).collect();
+    assert_eq!(vtimes,vec![0,10,15]);
+}
+
+#[test]
+fn name_and_copyright_events() {
+    use ::{MetaCommand,SMFWriter};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_midi_abs(0,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.set_name(0,"Piano".to_string()).unwrap();
+    builder.set_copyright(0,"Nobody".to_string()).unwrap();
+
+    let mut bytes = Vec::new();
+    SMFWriter::from_smf(builder.result()).write_all(&mut bytes).unwrap();
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    let track = &smf.tracks[0];
+    assert_eq!(track.name,Some("Piano".to_string()));
+    assert_eq!(track.copyright,Some("Nobody".to_string()));
+
+    let names: Vec<&Vec<u8>> = track.events.iter().filter_map(|e| match e.event {
+        Event::Meta(ref m) if m.command == MetaCommand::SequenceOrTrackName => Some(&m.data),
+        _ => None,
+    }).collect();
+    assert_eq!(names,vec![&b"Piano".to_vec()]);
+}
+
+#[test]
+fn fluent_build() {
+    let mut builder = SMFBuilder::new();
+    assert_eq!(builder.add_track(),0);
+    let track = builder.add_track();
+    assert_eq!(track,1);
+    builder.note_on_at(track,0,60,100,0).unwrap()
+        .note_on_at(track,0,64,100,0).unwrap()
+        .note_off_at(track,480,60,0,0).unwrap()
+        .note_off_at(track,480,64,0,0).unwrap();
+    let smf = builder.result();
+    assert!(smf.tracks[0].events.iter().all(|e| match e.event {
+        Event::Meta(_) => true,
+        Event::Midi(_) => false,
+    }));
+    let events: Vec<(u64,Vec<u8>)> = smf.tracks[1].events.iter().filter_map(|e| match e.event {
+        Event::Midi(ref m) => Some((e.vtime,m.data.clone())),
+        Event::Meta(_) => None,
+    }).collect();
+    assert_eq!(events,vec![(0,vec![0x90,60,100]),
+                           (0,vec![0x90,64,100]),
+                           (480,vec![0x80,60,0]),
+                           (0,vec![0x80,64,0])]);
+}
+
+#[test]
+fn division() {
+    use ::{SMFWriter,SMF};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    assert_eq!(builder.result().division,480);
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.set_division(96);
+    let mut bytes = Vec::new();
+    SMFWriter::from_smf(builder.result()).write_all(&mut bytes).unwrap();
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.division,96);
+}
+
+#[test]
+fn unsorted_static_track() {
+    let events = [
+        AbsoluteEvent::new_midi(25,MidiMessage::note_on(64,100,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(62,100,0)),
+        AbsoluteEvent::new_meta(10,MetaEvent::marker_text("b".to_string())),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(61,100,0)),
+    ];
+    let mut builder = SMFBuilder::new();
+    builder.add_static_track(events.iter());
+    let smf = builder.result();
+    let order: Vec<(u64,String)> = smf.tracks[0].events.iter().map(|e| (e.vtime,e.event.to_string())).collect();
+    assert_eq!(order,vec![(0,events[1].get_event().to_string()),
+                          (10,events[3].get_event().to_string()),
+                          (0,events[2].get_event().to_string()),
+                          (0,events[4].get_event().to_string()),
+                          (15,events[0].get_event().to_string())]);
+}
+
+#[test]
+fn builder_errors() {
+    let events = [AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0))];
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_static_track(events.iter());
+
+    let note = MidiMessage::note_on(60,100,0);
+    assert_eq!(builder.add_midi_abs(2,0,note.clone()),Err(BuilderError::NoSuchTrack(2)));
+    assert_eq!(builder.add_midi_rel(1,0,note.clone()),Err(BuilderError::StaticTrackNotMutable));
+    assert_eq!(builder.add_meta_abs(1,0,MetaEvent::end_of_track()),Err(BuilderError::StaticTrackNotMutable));
+    assert!(builder.note_on_at(1,0,60,100,0).is_err());
+
+    assert_eq!(builder.set_name(1,"a".to_string()),Ok(()));
+    assert_eq!(builder.set_name(1,"b".to_string()),Err(BuilderError::NameAlreadySet));
+    assert_eq!(builder.set_copyright(0,"a".to_string()),Ok(()));
+    assert_eq!(builder.set_copyright(0,"b".to_string()),Err(BuilderError::CopyrightAlreadySet));
+    assert_eq!(builder.set_copyright(5,"b".to_string()),Err(BuilderError::NoSuchTrack(5)));
+}
+
+#[test]
+fn mutate_absolute_events() {
+    use ::{SMFWriter,SMF};
+
+    let mut events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_off(60,0,0)),
+    ];
+    events[1].set_time(96);
+    if let Event::Midi(ref mut msg) = *events[0].get_event_mut() {
+        msg.data[1] = 62;
+    }
+    assert_eq!(events[1].get_time(),96);
+
+    let mut writer = SMFWriter::new_with_division(96);
+    writer.add_track(events.iter()).unwrap();
+    let mut bytes = Vec::new();
+    writer.write_all(&mut bytes).unwrap();
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    let track = &smf.tracks[0];
+    assert_eq!(track.events[0].event.to_string(),Event::Midi(MidiMessage::note_on(62,100,0)).to_string());
+    assert_eq!(track.events[1].vtime,96);
+}
+
+#[test]
+fn raw_events() {
+    let events = vec![
+        TrackEvent { vtime: 10, event: Event::Midi(MidiMessage::note_on(60,100,0)) },
+        TrackEvent { vtime: 0, event: Event::Midi(MidiMessage::note_on(64,100,0)) },
+        TrackEvent { vtime: 0, event: Event::Meta(MetaEvent::tempo_setting(400000)) },
+        TrackEvent { vtime: 96, event: Event::Midi(MidiMessage::note_off(60,0,0)) },
+    ];
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_track();
+    builder.add_meta_abs(1,5,MetaEvent::end_of_track()).unwrap();
+    builder.add_events_raw(0,events.clone()).unwrap();
+    builder.add_events_raw(0,vec![TrackEvent { vtime: 7, event: Event::Meta(MetaEvent::end_of_track()) }]).unwrap();
+    builder.add_events_raw(1,events.clone()).unwrap();
+    assert_eq!(builder.add_events_raw(2,Vec::new()),Err(BuilderError::NoSuchTrack(2)));
+    assert_eq!(builder.add_midi_rel(0,0,MidiMessage::note_on(60,100,0)),Err(BuilderError::StaticTrackNotMutable));
+    let smf = builder.result();
+
+    let deltas = |track: &Track| -> Vec<(u64,String)> {
+        track.events.iter().map(|e| (e.vtime,e.event.to_string())).collect()
+    };
+    let mut expected: Vec<(u64,String)> = events.iter().map(|e| (e.vtime,e.event.to_string())).collect();
+    expected.push((7,MetaEvent::end_of_track().to_string()));
+    assert_eq!(deltas(&smf.tracks[0]),expected);
+    assert_eq!(deltas(&smf.tracks[1])[0],(5,MetaEvent::end_of_track().to_string()));
+    assert_eq!(&deltas(&smf.tracks[1])[1..],&expected[..4]);
+}
+
+#[test]
+fn result_as() {
+    use ::{SMFWriter,SMF};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap().note_off_at(0,96,60,0,0).unwrap();
+    let smf = builder.result_as(SMFFormat::Single).unwrap();
+    assert_eq!(smf.format,SMFFormat::Single);
+    assert_eq!(smf.tracks.len(),1);
+
+    let mut bytes = Vec::new();
+    SMFWriter::from_smf(smf).write_all(&mut bytes).unwrap();
+    assert_eq!(&bytes[8..12],&[0,0,0,1]);
+    let read = SMF::from_reader(&mut &bytes[..]).unwrap();
+    assert_eq!(read.format,SMFFormat::Single);
+    assert_eq!(read.tracks[0].events.len(),3);
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_track();
+    assert_eq!(builder.result_as(SMFFormat::Single).err(),Some(BuilderError::NotSingleTrack(2)));
+    assert_eq!(SMFBuilder::new().result_as(SMFFormat::Single).err(),Some(BuilderError::NotSingleTrack(0)));
+    assert_eq!(SMFBuilder::new().result_as(SMFFormat::MultiSong).unwrap().format,SMFFormat::MultiSong);
+}
+
+#[test]
+fn conductor_track() {
+    use ::{MetaCommand};
+
+    let mut builder = SMFBuilder::new();
+    builder.set_division(480);
+    builder.add_track();
+    let mut conductor = ConductorTrackBuilder::new();
+    conductor.time_signature(1,4,4).unwrap().tempo(1,1,120.0);
+    conductor.time_signature(5,3,4).unwrap().tempo(5,2,90.0);
+    assert_eq!(conductor.ticks_at(480,1,1),0);
+    assert_eq!(conductor.ticks_at(480,2,1),1920);
+    assert_eq!(conductor.ticks_at(480,5,1),4*1920);
+    assert_eq!(conductor.ticks_at(480,5,2),4*1920+480);
+    assert_eq!(conductor.ticks_at(480,6,1),4*1920+1440);
+    conductor.add_to(&mut builder,0).unwrap();
+    assert_eq!(conductor.add_to(&mut builder,1),Err(BuilderError::NoSuchTrack(1)));
+    assert!(conductor.time_signature(7,3,3).is_err());
+    assert!(conductor.time_signature(7,3,0).is_err());
+    assert_eq!(conductor.time_signature(7,0,4).err(),Some(BuilderError::ZeroNumerator));
+
+    let mut smpte = SMFBuilder::new();
+    smpte.set_division(-0x1828);
+    smpte.add_track();
+    assert_eq!(conductor.add_to(&mut smpte,0),Err(BuilderError::InvalidDivision(-0x1828)));
+    let smf = builder.result();
+
+    let events: Vec<(u64,MetaCommand,Vec<u8>)> = smf.tracks[0].iter_absolute().map(|(time,event)| match event.event {
+        Event::Meta(ref meta) => (time,meta.command,meta.data.clone()),
+        Event::Midi(_) => panic!("expected only meta events"),
+    }).collect();
+    assert_eq!(events,vec![
+        (0,MetaCommand::TempoSetting,vec![0x07,0xA1,0x20]),
+        (0,MetaCommand::TimeSignature,vec![4,2,24,8]),
+        (7680,MetaCommand::TimeSignature,vec![3,2,24,8]),
+        (8160,MetaCommand::TempoSetting,vec![0x0A,0x2C,0x2B]),
+    ]);
+    let map = smf.build_tempo_map().unwrap();
+    assert_eq!(map.ticks_to_micros(8160),8500000);
+    assert_eq!(map.ticks_to_micros(8640),8500000 + 666667);
+
+    // a 6/8 bar is six eighth notes long
+    let mut conductor = ConductorTrackBuilder::new();
+    conductor.time_signature(1,6,8).unwrap();
+    assert_eq!(conductor.ticks_at(96,2,1),6*48);
+    assert_eq!(conductor.ticks_at(96,1,4),3*48);
+}
+
+#[test]
+fn midi_order_matches_message_order() {
+    let msgs = [
+        MidiMessage::note_on(60,100,0),
+        MidiMessage::note_on(60,90,0),
+        MidiMessage::note_off(64,0,0),
+        MidiMessage::control_change(7,100,0),
+        MidiMessage::from_bytes(vec![0xF8]),
+    ];
+    for a in msgs.iter() {
+        for b in msgs.iter() {
+            let abs_a = AbsoluteEvent::new_midi(0,a.clone());
+            let abs_b = AbsoluteEvent::new_midi(0,b.clone());
+            assert_eq!(abs_a.cmp(&abs_b),a.cmp(b));
+        }
+    }
+}
+
+#[test]
+fn midi_events_differing_in_data() {
+    let a = AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0));
+    let b = AbsoluteEvent::new_midi(0,MidiMessage::note_on(61,100,0));
+    let c = AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,90,0));
+    assert!(a != b);
+    assert!(a != c);
+    assert!(a == AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)));
+    assert!(a != AbsoluteEvent::new_midi(1,MidiMessage::note_on(60,100,0)));
+
+    // one byte messages can be compared too
+    let clock = AbsoluteEvent::new_midi(0,MidiMessage::from_bytes(vec![0xF8]));
+    assert!(clock == AbsoluteEvent::new_midi(0,MidiMessage::from_bytes(vec![0xF8])));
+    assert!(clock != a);
+}
+
+#[test]
+fn step_track() {
+    use ::Status;
+
+    let kick = StepCell::Note { note: 36, velocity: 110, channel: 9 };
+    let mut pattern = [StepCell::Rest; 16];
+    for step in (0..16).step_by(4) {
+        pattern[step] = kick;
+    }
+    let mut builder = SMFBuilder::new();
+    assert_eq!(builder.add_step_track(4,&pattern),Ok(0));
+    builder.add_midi_abs(0,1920,MidiMessage::note_on(49,90,9)).unwrap();
+    let smf = builder.result();
+
+    let track = &smf.tracks[0];
+    assert_eq!(track.events.len(),9);
+    let ons: Vec<u64> = track.iter_absolute()
+        .filter(|&(_,event)| event.event.as_midi().is_some_and(|msg| msg.status() == Status::NoteOn && msg.note() == Some(36)))
+        .map(|(time,_)| time).collect();
+    assert_eq!(ons,vec![0,480,960,1440]);
+    let offs: Vec<u64> = track.iter_absolute()
+        .filter(|&(_,event)| event.event.as_midi().is_some_and(|msg| msg.status() == Status::NoteOff))
+        .map(|(time,_)| time).collect();
+    assert_eq!(offs,vec![120,600,1080,1560]);
+
+    // steps that don't divide the beat evenly
+    let mut builder = SMFBuilder::new();
+    builder.set_division(96);
+    builder.add_step_track(7,&[kick,kick,StepCell::Rest,kick]).unwrap();
+    let smf = builder.result();
+    let times: Vec<u64> = smf.tracks[0].iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![0,13,13,27,41,54]);
+
+    let mut builder = SMFBuilder::new();
+    assert_eq!(builder.add_step_track(0,&pattern),Err(BuilderError::NoStepsPerBeat));
+    builder.set_division(-0x1828);
+    assert_eq!(builder.add_step_track(4,&pattern),Err(BuilderError::InvalidDivision(-0x1828)));
+    assert!(builder.result().tracks.is_empty());
+}
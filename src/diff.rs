@@ -0,0 +1,174 @@
+use ::{Event,SMF,SMFFormat};
+
+/// A difference between two SMFs found by `SMF::diff`.  Times are
+/// absolute times in ticks from the start of the track, and events
+/// are compared by content, so differences in how the files were
+/// encoded (e.g. running status) don't show up.
+#[derive(Debug,Clone,PartialEq)]
+pub enum DiffEntry {
+    /// The SMFs have different formats
+    Format { from: SMFFormat, to: SMFFormat },
+    /// The SMFs have different divisions
+    Division { from: i16, to: i16 },
+    /// An event of the first SMF isn't in the second one
+    Missing { track: usize, time: u64, event: Event },
+    /// An event of the second SMF isn't in the first one
+    Extra { track: usize, time: u64, event: Event },
+    /// An event at `time` is `from` in the first SMF but `to` in the
+    /// second one.  Only midi messages with the same status byte, or
+    /// meta events with the same command, are paired up like this.
+    Changed { track: usize, time: u64, from: Event, to: Event },
+}
+
+pub fn diff(from: &SMF, to: &SMF) -> Vec<DiffEntry> {
+    let mut entries = Vec::new();
+    if from.format != to.format {
+        entries.push(DiffEntry::Format { from: from.format, to: to.format });
+    }
+    if from.division != to.division {
+        entries.push(DiffEntry::Division { from: from.division, to: to.division });
+    }
+    for track in 0..from.tracks.len().max(to.tracks.len()) {
+        diff_track(track,&track_events(from,track),&track_events(to,track),&mut entries);
+    }
+    entries
+}
+
+// (absolute time, event) for each event of track number `track`.  A
+// track that's only in one SMF is compared to an empty one.
+fn track_events(smf: &SMF, track: usize) -> Vec<(u64,&Event)> {
+    match smf.tracks.get(track) {
+        Some(track) => track.iter_absolute().map(|(time,event)| (time,&event.event)).collect(),
+        None => Vec::new(),
+    }
+}
+
+// Take the events at `time` from the front of `events`
+fn take_at<'a>(events: &[(u64,&'a Event)], pos: &mut usize, time: u64) -> Vec<&'a Event> {
+    let mut taken = Vec::new();
+    while let Some(&(t,event)) = events.get(*pos) {
+        if t != time {
+            break;
+        }
+        taken.push(event);
+        *pos += 1;
+    }
+    taken
+}
+
+// True for midi messages with the same status byte, and for meta
+// events with the same command
+fn same_kind(a: &Event, b: &Event) -> bool {
+    match (a,b) {
+        (Event::Midi(a),Event::Midi(b)) => a.raw_status() == b.raw_status(),
+        (Event::Meta(a),Event::Meta(b)) => a.command == b.command,
+        _ => false,
+    }
+}
+
+fn diff_track(track: usize, from: &[(u64,&Event)], to: &[(u64,&Event)], entries: &mut Vec<DiffEntry>) {
+    let (mut i,mut j) = (0,0);
+    while i < from.len() || j < to.len() {
+        let time = match (from.get(i),to.get(j)) {
+            (Some(&(a,_)),Some(&(b,_))) => a.min(b),
+            (Some(&(a,_)),None) => a,
+            (None,Some(&(b,_))) => b,
+            (None,None) => break,
+        };
+        // events at the same time can be in any order, so only those
+        // that are in one list but not the other are differences
+        let mut old = take_at(from,&mut i,time);
+        let mut new = take_at(to,&mut j,time);
+        old.retain(|event| match new.iter().position(|other| other == event) {
+            Some(pos) => {
+                new.remove(pos);
+                false
+            }
+            None => true,
+        });
+        // what's left of each is a change if it matches an event of
+        // the same kind in the other
+        for event in old {
+            match new.iter().position(|other| same_kind(event,other)) {
+                Some(pos) => {
+                    let other = new.remove(pos);
+                    entries.push(DiffEntry::Changed { track, time, from: event.clone(), to: other.clone() });
+                }
+                None => entries.push(DiffEntry::Missing { track, time, event: event.clone() }),
+            }
+        }
+        for event in new {
+            entries.push(DiffEntry::Extra { track, time, event: event.clone() });
+        }
+    }
+}
+
+#[test]
+fn diff_transposed() {
+    use ::{MetaEvent,MidiMessage,OutOfRange,SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0,0,MetaEvent::tempo_setting(500000)).unwrap();
+    builder.add_midi_abs(0,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(0,96,MidiMessage::note_off(60,0,0)).unwrap();
+    builder.add_midi_abs(0,96,MidiMessage::control_change(7,100,0)).unwrap();
+    builder.add_meta_abs(0,100,MetaEvent::end_of_track()).unwrap();
+    let smf = builder.result();
+    assert!(diff(&smf,&smf.clone()).is_empty());
+
+    let mut transposed = smf.clone();
+    transposed.tracks[0].transpose(2,OutOfRange::Clamp);
+    assert_eq!(smf.diff(&transposed),vec![
+        DiffEntry::Changed {
+            track: 0, time: 0,
+            from: Event::Midi(MidiMessage::note_on(60,100,0)),
+            to: Event::Midi(MidiMessage::note_on(62,100,0)),
+        },
+        DiffEntry::Changed {
+            track: 0, time: 96,
+            from: Event::Midi(MidiMessage::note_off(60,0,0)),
+            to: Event::Midi(MidiMessage::note_off(62,0,0)),
+        },
+    ]);
+
+    transposed.tracks[0].remove(0);
+    transposed.tracks.push(transposed.tracks[0].clone());
+    transposed.division = 96;
+    let entries = smf.diff(&transposed);
+    assert_eq!(entries[0],DiffEntry::Division { from: smf.division, to: 96 });
+    assert_eq!(entries[1],DiffEntry::Missing {
+        track: 0, time: 0,
+        event: Event::Meta(MetaEvent::tempo_setting(500000)),
+    });
+    assert!(entries[4..].iter().all(|entry| matches!(*entry,DiffEntry::Extra { track: 1, .. })));
+    assert_eq!(entries.len(),4 + transposed.tracks[1].events.len());
+}
+
+#[test]
+fn diff_ignores_running_status() {
+    use ::{MidiMessage,SMFBuilder,SMFWriter};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_midi_abs(0,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(0,0,MidiMessage::note_on(64,100,0)).unwrap();
+    builder.add_midi_abs(0,96,MidiMessage::note_on(60,0,0)).unwrap();
+    builder.add_midi_abs(0,96,MidiMessage::note_on(64,0,0)).unwrap();
+    let smf = builder.result();
+
+    let write = |running_status| {
+        let mut writer = SMFWriter::new_with_division_and_format(smf.format,smf.division);
+        writer.set_running_status(running_status);
+        writer.add_smf_track(&smf.tracks[0]).unwrap();
+        let mut bytes = Vec::new();
+        writer.write_all(&mut bytes).unwrap();
+        bytes
+    };
+    let plain = write(false);
+    let running = write(true);
+    assert!(running.len() < plain.len());
+    let plain = SMF::from_reader(&mut &plain[..]).unwrap();
+    let running = SMF::from_reader(&mut &running[..]).unwrap();
+    assert!(plain.diff(&running).is_empty());
+}
@@ -13,25 +13,59 @@
 //! http://www.midi.org/techspecs/midimessages.php<br/>
 //! For a description of the underlying format of meta messages see:<br/>
 //! http://cs.fit.edu/~ryan/cse4051/projects/midi/midi.html#meta_event
+//!
+//! The `std` feature is on by default.  Without it rimd builds for
+//! `no_std` targets with `alloc`, and only the midi and meta message
+//! types and the utility functions that don't need `std` are
+//! available.  Use `MidiMessage::from_slice` and
+//! `MetaEvent::from_slice` to parse messages from bytes.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(not(feature = "std"))]
+extern crate core as std;
+#[cfg(not(feature = "std"))]
+#[macro_use] extern crate alloc;
 
 extern crate byteorder;
+#[cfg(feature = "std")]
 extern crate encoding;
 extern crate num_traits;
 #[macro_use] extern crate num_derive;
+#[cfg(feature = "serde")]
+#[macro_use] extern crate serde;
 
+#[cfg(feature = "std")]
+use std::collections::{HashMap,HashSet,VecDeque};
+#[cfg(feature = "std")]
 use std::error;
-use std::convert::From;
+#[cfg(feature = "std")]
+use std::convert::TryFrom;
+#[cfg(feature = "std")]
 use std::fs::File;
-use std::io::{Error,Read};
+#[cfg(feature = "std")]
+use std::io::{Cursor,Error,Read};
+#[cfg(feature = "std")]
 use std::path::Path;
 
 use std::fmt;
+#[cfg(feature = "std")]
 use std::string::FromUtf8Error;
 
+#[cfg(feature = "std")]
+use encoding::EncodingRef;
+#[cfg(feature = "std")]
+use encoding::all::ISO_8859_1;
+
+#[cfg(feature = "std")]
+use util::XorShift;
+
 pub use midi:: {
     Status,
+    StatusCategory,
     MidiError,
     MidiMessage,
+    MidiStreamParser,
     STATUS_MASK,
     CHANNEL_MASK,
     make_status,
@@ -41,33 +75,93 @@ pub use meta:: {
     MetaCommand,
     MetaError,
     MetaEvent,
+    Mode,
+    SmpteOffset,
 };
 
+#[cfg(feature = "std")]
 pub use builder:: {
+    BuilderError,
+    ConductorTrackBuilder,
     SMFBuilder,
+    StepCell,
     AbsoluteEvent,
 };
 
-use reader:: {
+#[cfg(feature = "std")]
+pub use reader:: {
+    AfterEndOfTrack,
+    PartialSMF,
+    ReadOptions,
+    SMFMeta,
     SMFReader,
+    SMFStreamReader,
+    SMFWarning,
+    UndefinedStatus,
 };
 
+#[cfg(feature = "std")]
 pub use writer:: {
     SMFWriter,
+    MAX_VTIME,
+};
+
+#[cfg(feature = "std")]
+pub use tempo:: {
+    PositionMap,
+    TempoMap,
+    DEFAULT_TEMPO,
+};
+
+#[cfg(feature = "std")]
+pub use diff:: {
+    DiffEntry,
+};
+
+#[cfg(feature = "std")]
+pub use validate:: {
+    ValidationWarning,
 };
 
 pub use util:: {
+    combine_7bit,
+    controller_name,
+    controller_number,
+    decode_vlq_slice,
+    encode_vlq,
+    gm_drum_name,
+    gm_program_name,
+    key_name,
+    name_to_note_num,
+    split_14bit,
+};
+
+#[cfg(feature = "std")]
+pub use util:: {
+    decode_vlq,
     note_num_to_name,
+    note_num_to_freq,
+    note_num_to_freq_with_tuning,
 };
 
+#[cfg(feature = "std")]
 mod builder;
+#[cfg(feature = "std")]
+mod diff;
 mod midi;
 mod meta;
+#[cfg(feature = "std")]
 mod reader;
+#[cfg(feature = "std")]
+mod tempo;
+#[cfg(feature = "std")]
 mod writer;
 mod util;
+#[cfg(feature = "std")]
+mod validate;
 
 /// Format of the SMF
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug,Clone,Copy,PartialEq)]
 pub enum SMFFormat {
     /// single track file format
@@ -89,13 +183,69 @@ impl fmt::Display for SMFFormat {
     }
 }
 
+/// The unit of time used for delta timing in an SMF
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Division {
+    /// The number of ticks per beat (quarter note)
+    TicksPerBeat(u16),
+    /// SMPTE compatible units. `fps` is the number of frames per
+    /// second (24, 25, 29 or 30, where 29 means 30 drop frame,
+    /// i.e. 29.97 fps), and `ticks_per_frame` is the resolution
+    /// within a frame
+    Smpte { fps: u8, ticks_per_frame: u8 },
+}
+
+impl Division {
+    /// Decode the raw division value of an SMF header.  For SMPTE
+    /// units the upper byte holds the negative frames per second and
+    /// the lower byte the ticks per frame
+    pub fn from_i16(division: i16) -> Division {
+        if division >= 0 {
+            Division::TicksPerBeat(division as u16)
+        } else {
+            Division::Smpte {
+                fps: ((division >> 8) as i8).wrapping_neg() as u8,
+                ticks_per_frame: division as u8,
+            }
+        }
+    }
+
+    /// Encode this division as the raw value stored in an SMF
+    /// header.  Ticks per beat must be less than 0x8000.
+    pub fn to_i16(&self) -> i16 {
+        match *self {
+            Division::TicksPerBeat(ticks) => (ticks & 0x7FFF) as i16,
+            Division::Smpte { fps, ticks_per_frame } => {
+                ((((fps as i8).wrapping_neg() as u8 as u16) << 8) | ticks_per_frame as u16) as i16
+            }
+        }
+    }
+}
+
+impl fmt::Display for Division {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Division::TicksPerBeat(ticks) => write!(f, "{} ticks/beat", ticks),
+            Division::Smpte { fps: 29, ticks_per_frame } => {
+                write!(f, "SMPTE 29.97 fps (drop frame), {} ticks/frame", ticks_per_frame)
+            }
+            Division::Smpte { fps, ticks_per_frame } => {
+                write!(f, "SMPTE {} fps, {} ticks/frame", fps, ticks_per_frame)
+            }
+        }
+    }
+}
+
 /// An event can be either a midi message or a meta event
-#[derive(Debug,Clone)]
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug,Clone,PartialEq,Eq,Hash)]
 pub enum Event {
     Midi(MidiMessage),
     Meta(MetaEvent),
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for Event {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
@@ -105,10 +255,16 @@ impl fmt::Display for Event {
     }
 }
 
+#[cfg(feature = "std")]
 impl Event {
     /// Return the number of bytes this event uses.
     pub fn len(&self) -> usize {
         match *self {
+            Event::Midi(ref m) if m.is_sysex() || m.is_sysex_escape() => {
+                // SysEx data is preceded by its length after the status byte
+                let v = SMFWriter::vtime_to_vec((m.data.len() - 1) as u64);
+                v.len() + m.data.len()
+            }
             Event::Midi(ref m) => { m.data.len() }
             Event::Meta(ref m) => {
                 let v = SMFWriter::vtime_to_vec(m.length);
@@ -117,9 +273,58 @@ impl Event {
             }
         }
     }
+
+    /// Return true if this is a midi event, false if it's a meta event
+    pub fn is_midi(&self) -> bool {
+        match *self {
+            Event::Midi(_) => true,
+            Event::Meta(_) => false,
+        }
+    }
+
+    /// Return true if this is a meta event, false if it's a midi event
+    pub fn is_meta(&self) -> bool {
+        !self.is_midi()
+    }
+
+    /// Get the midi message of this event, or None if it's a meta event
+    pub fn as_midi(&self) -> Option<&MidiMessage> {
+        match *self {
+            Event::Midi(ref msg) => Some(msg),
+            Event::Meta(_) => None,
+        }
+    }
+
+    /// Get the midi message of this event mutably, or None if it's a
+    /// meta event
+    pub fn as_midi_mut(&mut self) -> Option<&mut MidiMessage> {
+        match *self {
+            Event::Midi(ref mut msg) => Some(msg),
+            Event::Meta(_) => None,
+        }
+    }
+
+    /// Get the meta event of this event, or None if it's a midi event
+    pub fn as_meta(&self) -> Option<&MetaEvent> {
+        match *self {
+            Event::Meta(ref meta) => Some(meta),
+            Event::Midi(_) => None,
+        }
+    }
+
+    /// Get the meta event of this event mutably, or None if it's a
+    /// midi event
+    pub fn as_meta_mut(&mut self) -> Option<&mut MetaEvent> {
+        match *self {
+            Event::Meta(ref mut meta) => Some(meta),
+            Event::Midi(_) => None,
+        }
+    }
 }
 
 /// An event occuring in the track.
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug,Clone)]
 pub struct TrackEvent {
     /// A delta offset, indicating how many ticks after the previous
@@ -130,12 +335,14 @@ pub struct TrackEvent {
 }
 
 
+#[cfg(feature = "std")]
 impl fmt::Display for TrackEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "vtime: {}\t{}",self.vtime,self.event)
     }
 }
 
+#[cfg(feature = "std")]
 impl TrackEvent {
     pub fn fmt_with_time_offset(&self, cur_time: u64) -> String {
         format!("time: {}\t{}",(self.vtime+cur_time),self.event)
@@ -150,16 +357,21 @@ impl TrackEvent {
 }
 
 /// A sequence of midi/meta events
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 pub struct Track {
     /// Optional copyright notice
     pub copyright: Option<String>,
     /// Optional name for this track
     pub name: Option<String>,
+    /// Optional name of the instrument this track is for
+    pub instrument: Option<String>,
     /// Vector of the events in this track
     pub events: Vec<TrackEvent>
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for Track {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Track, copyright: {}, name: {}",
@@ -174,8 +386,378 @@ impl fmt::Display for Track {
     }
 }
 
+/// A note, built from a NoteOn and its matching NoteOff by
+/// `Track::notes`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Note {
+    pub channel: u8,
+    pub pitch: u8,
+    /// The velocity of the NoteOn
+    pub velocity: u8,
+    /// Absolute time of the NoteOn in ticks
+    pub start_tick: u64,
+    pub duration_ticks: u64,
+}
+
+/// What to do with notes that would be moved outside of the valid
+/// range of 0..127 by `Track::transpose`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum OutOfRange {
+    /// Move the note to 0 or 127, whichever is nearest
+    Clamp,
+    /// Remove the event from the track
+    Skip,
+}
+
+#[cfg(feature = "std")]
+impl Track {
+    /// Return the length of this track in ticks, i.e. the sum of
+    /// the time offsets of all its events
+    pub fn duration_ticks(&self) -> u64 {
+        self.events.iter().map(|e| e.vtime).sum()
+    }
+
+    /// Iterate over the midi events of this track that are on
+    /// `channel`.  Meta events and system messages are skipped.  Note
+    /// that the `vtime` of each event is still relative to the event
+    /// before it in the whole track.
+    pub fn events_on_channel(&self, channel: u8) -> impl Iterator<Item=&TrackEvent> {
+        self.events.iter().filter(move |event| match event.event {
+            Event::Midi(ref msg) => msg.channel() == Some(channel),
+            Event::Meta(_) => false,
+        })
+    }
+
+    /// Get the set of channels that the channel voice messages of
+    /// this track are on
+    pub fn channels_used(&self) -> HashSet<u8> {
+        self.events.iter().filter_map(|event| match event.event {
+            Event::Midi(ref msg) => msg.channel(),
+            Event::Meta(_) => None,
+        }).collect()
+    }
+
+    /// Get the program of each channel that has a ProgramChange
+    /// message in this track, as a map from channel to the last
+    /// program set on it
+    pub fn programs_used(&self) -> HashMap<u8,u8> {
+        let mut programs = HashMap::new();
+        for event in self.events.iter() {
+            if let Event::Midi(ref msg) = event.event {
+                if let (Some(channel),Some(program)) = (msg.channel(),msg.program()) {
+                    programs.insert(channel,program);
+                }
+            }
+        }
+        programs
+    }
+
+    /// Pair up the NoteOn and NoteOff messages (or NoteOns with
+    /// velocity 0) of this track into notes, ordered by start time.
+    /// If several notes of the same pitch on the same channel
+    /// overlap, each NoteOff ends the earliest one still sounding.
+    /// Notes that are never turned off last until the end of the
+    /// track.
+    pub fn notes(&self) -> Vec<Note> {
+        let mut notes: Vec<Note> = Vec::new();
+        // indexes into `notes` of the sounding notes, by channel and pitch
+        let mut sounding: HashMap<(u8,u8),VecDeque<usize>> = HashMap::new();
+        let mut time = 0;
+        for event in self.events.iter() {
+            time += event.vtime;
+            if let Event::Midi(ref msg) = event.event {
+                let (channel,pitch) = match (msg.channel(),msg.note()) {
+                    (Some(channel),Some(pitch)) => (channel,pitch),
+                    _ => continue,
+                };
+                match msg.effective_status() {
+                    Status::NoteOn => {
+                        sounding.entry((channel,pitch)).or_default().push_back(notes.len());
+                        notes.push(Note {
+                            channel,
+                            pitch,
+                            velocity: msg.velocity().unwrap_or(0),
+                            start_tick: time,
+                            duration_ticks: 0,
+                        });
+                    }
+                    Status::NoteOff => {
+                        let index = sounding.get_mut(&(channel,pitch)).and_then(|s| s.pop_front());
+                        if let Some(index) = index {
+                            notes[index].duration_ticks = time - notes[index].start_tick;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for index in sounding.values().flat_map(|s| s.iter()) {
+            notes[*index].duration_ticks = time - notes[*index].start_tick;
+        }
+        notes
+    }
+
+    /// Transpose every NoteOn, NoteOff and PolyphonicAftertouch
+    /// message in this track by `semitones`.  Notes that would end up
+    /// outside of 0..127 are handled according to `policy`.  Other
+    /// events are left untouched.
+    pub fn transpose(&mut self, semitones: i8, policy: OutOfRange) {
+        let shift = |note: u8| note as i16 + semitones as i16;
+        if policy == OutOfRange::Skip {
+            self.retain_events(|event| match event.event {
+                Event::Midi(ref msg) => msg.note().is_none_or(|note| (0..=127).contains(&shift(note))),
+                Event::Meta(_) => true,
+            });
+        }
+        for event in self.events.iter_mut() {
+            if let Event::Midi(ref mut msg) = event.event {
+                if let Some(note) = msg.note() {
+                    msg.data[1] = shift(note).clamp(0,127) as u8;
+                }
+            }
+        }
+    }
+
+    /// Move every channel voice message on channel `from` to channel
+    /// `to`.  Other events are left untouched.
+    pub fn remap_channel(&mut self, from: u8, to: u8) {
+        let mut map = [0; 16];
+        for (channel,slot) in map.iter_mut().enumerate() {
+            *slot = channel as u8;
+        }
+        map[(from & CHANNEL_MASK) as usize] = to;
+        self.remap_all_channels(&map);
+    }
+
+    /// Move every channel voice message to the channel given for its
+    /// current channel in `map`, i.e. channel `c` becomes `map[c]`.
+    /// Other events are left untouched.
+    pub fn remap_all_channels(&mut self, map: &[u8;16]) {
+        for event in self.events.iter_mut() {
+            if let Event::Midi(ref mut msg) = event.event {
+                if let Some(channel) = msg.channel() {
+                    msg.data[0] = (msg.data[0] & STATUS_MASK) | (map[channel as usize] & CHANNEL_MASK);
+                }
+            }
+        }
+    }
+
+    /// Merge this track and `other` into a new track, keeping the
+    /// absolute time of every event.  Meta events sort before midi
+    /// events at the same time, and the EndOfTrack events of both
+    /// tracks are replaced by a single one at the latest time.  The
+    /// name, copyright and instrument are taken from this track if
+    /// set, or else from `other`.
+    pub fn merge(&self, other: &Track) -> Track {
+        Track {
+            copyright: self.copyright.clone().or_else(|| other.copyright.clone()),
+            name: self.name.clone().or_else(|| other.name.clone()),
+            instrument: self.instrument.clone().or_else(|| other.instrument.clone()),
+            events: merge_events(self.absolute_events().chain(other.absolute_events())),
+        }
+    }
+
+    /// Iterate over the events of this track along with their
+    /// absolute time in ticks from the start of the track
+    pub fn iter_absolute(&self) -> impl Iterator<Item=(u64,&TrackEvent)> {
+        self.events.iter().scan(0,|time,event| {
+            *time += event.vtime;
+            Some((*time,event))
+        })
+    }
+
+    /// Add some random variation to the timing and velocity of the
+    /// notes in this track, to make it sound less mechanical.  Each
+    /// note is moved by up to `timing_spread_ticks` either way (but
+    /// not before the start of the track), along with its NoteOff so
+    /// its length doesn't change, and its velocity is changed by up
+    /// to `velocity_spread` while staying in 1..127.  The same `seed`
+    /// always gives the same result.
+    pub fn humanize(&mut self, timing_spread_ticks: u64, velocity_spread: u8, seed: u64) {
+        let mut rng = XorShift::new(seed);
+        // offsets of the notes that are currently on, by channel and note
+        let mut on: HashMap<(u8,u8),VecDeque<i64>> = HashMap::new();
+        let mut timed = Vec::with_capacity(self.events.len());
+        let mut time = 0;
+        for mut event in self.events.drain(..) {
+            time += event.vtime;
+            let mut offset = 0;
+            if let Event::Midi(ref mut msg) = event.event {
+                if let (Some(channel),Some(note)) = (msg.channel(),msg.note()) {
+                    match msg.effective_status() {
+                        Status::NoteOn => {
+                            offset = rng.spread(timing_spread_ticks).max(-(time as i64));
+                            let velocity = msg.data[2] as i64 + rng.spread(velocity_spread as u64);
+                            msg.data[2] = velocity.clamp(1,127) as u8;
+                            on.entry((channel,note)).or_default().push_back(offset);
+                        }
+                        Status::NoteOff => {
+                            offset = on.get_mut(&(channel,note)).and_then(|offsets| offsets.pop_front()).unwrap_or(0);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            let is_eot = match event.event {
+                Event::Meta(ref meta) => meta.command == MetaCommand::EndOfTrack,
+                Event::Midi(_) => false,
+            };
+            timed.push((is_eot,(time as i64 + offset).max(0) as u64,event));
+        }
+        // a stable sort keeps the order of events that end up at the
+        // same time, and keeps any EndOfTrack last
+        timed.sort_by_key(|&(is_eot,time,_)| (is_eot,time));
+        let mut last = 0;
+        for (_,time,mut event) in timed {
+            let time = time.max(last);
+            event.vtime = time - last;
+            last = time;
+            self.events.push(event);
+        }
+    }
+
+    /// Insert `event` at absolute time `abs_time` in ticks, after
+    /// any events already at that time.  The delta time of the event
+    /// after it is reduced so the absolute times of all other events
+    /// don't change.  If the track ends with an EndOfTrack event that
+    /// is before `abs_time`, the end is moved so it stays last.
+    pub fn insert_at(&mut self, abs_time: u64, event: Event) {
+        let mut time = 0;
+        let mut index = self.events.len();
+        for (i,existing) in self.events.iter().enumerate() {
+            if time + existing.vtime > abs_time {
+                index = i;
+                break;
+            }
+            time += existing.vtime;
+        }
+        let ends_track = |event: &TrackEvent| event.event.as_meta().is_some_and(|meta| meta.command == MetaCommand::EndOfTrack);
+        if index == self.events.len() && self.events.last().is_some_and(ends_track) {
+            index -= 1;
+            time -= self.events[index].vtime;
+            self.events[index].vtime = abs_time - time;
+        }
+        let vtime = abs_time - time;
+        if let Some(next) = self.events.get_mut(index) {
+            next.vtime -= vtime;
+        }
+        self.events.insert(index,TrackEvent { vtime, event });
+    }
+
+    /// Remove the event at `index`, adding its delta time to the
+    /// event after it so the absolute times of the other events don't
+    /// change.  Returns the removed event, or None if `index` is past
+    /// the end of the track.
+    pub fn remove(&mut self, index: usize) -> Option<TrackEvent> {
+        if index >= self.events.len() {
+            return None;
+        }
+        let removed = self.events.remove(index);
+        if let Some(next) = self.events.get_mut(index) {
+            next.vtime += removed.vtime;
+        }
+        Some(removed)
+    }
+
+    /// Apply `f` to the velocity of every NoteOn in this track, for
+    /// example to compress or expand its dynamics.  The result is
+    /// clamped to 1..127.  NoteOffs and NoteOns with a velocity of 0
+    /// are left alone, so they still end their notes.
+    pub fn map_velocity<F>(&mut self, f: F) where F: Fn(u8) -> u8 {
+        for event in self.events.iter_mut() {
+            if let Some(msg) = event.event.as_midi_mut() {
+                if msg.effective_status() == Status::NoteOn {
+                    msg.data[2] = f(msg.data[2]).clamp(1,127);
+                }
+            }
+        }
+    }
+
+    /// Get the events of this track whose absolute time is in
+    /// `[start,end)`, along with their absolute time in ticks
+    pub fn events_in_range(&self, start: u64, end: u64) -> Vec<(u64,&TrackEvent)> {
+        self.iter_absolute()
+            .skip_while(|&(time,_)| time < start)
+            .take_while(|&(time,_)| time < end)
+            .collect()
+    }
+
+    /// Get the text of every LyricText event in this track along with
+    /// its absolute time in ticks
+    pub fn lyrics(&self) -> Vec<(u64,String)> {
+        self.lyrics_with(ISO_8859_1)
+    }
+
+    /// Get the text of every LyricText event in this track, decoded
+    /// with `encoding`, along with its absolute time in ticks
+    pub fn lyrics_with(&self, encoding: EncodingRef) -> Vec<(u64,String)> {
+        self.text_events(MetaCommand::LyricText,encoding)
+    }
+
+    /// Get the text of every MarkerText event in this track along with its
+    /// absolute time in ticks
+    pub fn markers(&self) -> Vec<(u64,String)> {
+        self.markers_with(ISO_8859_1)
+    }
+
+    /// Get the text of every MarkerText event in this track, decoded
+    /// with `encoding`, along with its absolute time in ticks
+    pub fn markers_with(&self, encoding: EncodingRef) -> Vec<(u64,String)> {
+        self.text_events(MetaCommand::MarkerText,encoding)
+    }
+
+    // the decoded text of the meta events with `command`, with their
+    // absolute times
+    fn text_events(&self, command: MetaCommand, encoding: EncodingRef) -> Vec<(u64,String)> {
+        self.iter_absolute().filter_map(|(time,event)| match event.event {
+            Event::Meta(ref meta) if meta.command == command => Some((time,meta.text(encoding))),
+            _ => None,
+        }).collect()
+    }
+
+    // the events of this track with their absolute times
+    fn absolute_events(&self) -> impl Iterator<Item=(u64,&Event)> {
+        self.iter_absolute().map(|(time,event)| (time,&event.event))
+    }
+
+    /// Keep only the events for which `f` returns true.  The time
+    /// offset of each removed event is added to the next kept event,
+    /// so the remaining events stay at the same absolute time.
+    pub fn retain_events<F: FnMut(&TrackEvent) -> bool>(&mut self, mut f: F) {
+        let mut carry = 0;
+        let mut events = Vec::with_capacity(self.events.len());
+        for mut event in self.events.drain(..) {
+            if f(&event) {
+                event.vtime += carry;
+                carry = 0;
+                events.push(event);
+            } else {
+                carry += event.vtime;
+            }
+        }
+        self.events = events;
+    }
+
+    /// Remove each event that is identical to the event right before
+    /// it and at the same time, like a repeated program change.
+    /// Events are identical if they have the same bytes, or for meta
+    /// events the same command and data.
+    pub fn dedup_consecutive(&mut self) {
+        let mut events: Vec<TrackEvent> = Vec::with_capacity(self.events.len());
+        for event in self.events.drain(..) {
+            // a duplicate has a vtime of 0, so no time needs to be carried over
+            let duplicate = event.vtime == 0 && events.last().is_some_and(|prev| prev.event == event.event);
+            if !duplicate {
+                events.push(event);
+            }
+        }
+        self.events = events;
+    }
+}
+
 
 /// An error that occured in parsing an SMF
+#[cfg(feature = "std")]
 #[derive(Debug)]
 pub enum SMFError {
     InvalidSMFFile(&'static str),
@@ -184,30 +766,35 @@ pub enum SMFError {
     Error(Error),
 }
 
+#[cfg(feature = "std")]
 impl From<Error> for SMFError {
     fn from(err: Error) -> SMFError {
         SMFError::Error(err)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<MidiError> for SMFError {
     fn from(err: MidiError) -> SMFError {
         SMFError::MidiError(err)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<MetaError> for SMFError {
     fn from(err: MetaError) -> SMFError {
         SMFError::MetaError(err)
     }
 }
 
+#[cfg(feature = "std")]
 impl From<FromUtf8Error> for SMFError {
     fn from(_: FromUtf8Error) -> SMFError {
         SMFError::InvalidSMFFile("Invalid UTF8 data in file")
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for SMFError {
     fn description(&self) -> &str {
         match *self {
@@ -228,6 +815,7 @@ impl error::Error for SMFError {
     }
 }
 
+#[cfg(feature = "std")]
 impl fmt::Display for SMFError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
        match *self {
@@ -239,7 +827,110 @@ impl fmt::Display for SMFError {
     }
 }
 
+/// An error that can occur appending one SMF to another with
+/// `SMF::append`
+#[cfg(feature = "std")]
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum AppendError {
+    /// The SMFs have different formats
+    FormatMismatch,
+    /// The SMFs have different divisions
+    DivisionMismatch,
+}
+
+#[cfg(feature = "std")]
+impl error::Error for AppendError {
+    fn description(&self) -> &str {
+        match *self {
+            AppendError::FormatMismatch => "SMFs have different formats",
+            AppendError::DivisionMismatch => "SMFs have different divisions",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for AppendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            AppendError::FormatMismatch => write!(f,"SMFs have different formats"),
+            AppendError::DivisionMismatch => write!(f,"SMFs have different divisions"),
+        }
+    }
+}
+
+/// An error that can occur changing the division of an SMF with
+/// `SMF::rescale_division`
+#[cfg(feature = "std")]
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum RescaleError {
+    /// The division of the SMF is in SMPTE units
+    SmpteDivision,
+    /// The new division is 0 or too large for an SMF (over 0x7FFF)
+    InvalidDivision(u16),
+}
+
+#[cfg(feature = "std")]
+impl error::Error for RescaleError {
+    fn description(&self) -> &str {
+        match *self {
+            RescaleError::SmpteDivision => "SMF division is in SMPTE units",
+            RescaleError::InvalidDivision(_) => "Invalid division",
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for RescaleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RescaleError::SmpteDivision => write!(f,"SMF division is in SMPTE units"),
+            RescaleError::InvalidDivision(division) => write!(f,"Invalid division: {}",division),
+        }
+    }
+}
+
+// Iterator over the events of several tracks in time order, see
+// `SMF::iter_merged`
+#[cfg(feature = "std")]
+struct MergedEvents<'a> {
+    tracks: &'a [Track],
+    // index of the next event and absolute time of the last event
+    // returned, for each track
+    positions: Vec<(usize,u64)>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> Iterator for MergedEvents<'a> {
+    type Item = (u64,usize,&'a Event);
+
+    fn next(&mut self) -> Option<(u64,usize,&'a Event)> {
+        let mut next: Option<(u64,usize)> = None;
+        for (i,track) in self.tracks.iter().enumerate() {
+            let (index,time) = self.positions[i];
+            if let Some(event) = track.events.get(index) {
+                let time = time + event.vtime;
+                // only replace on a strictly earlier time so that ties
+                // go to the lowest track index
+                let earlier = match next {
+                    Some((next_time,_)) => time < next_time,
+                    None => true,
+                };
+                if earlier {
+                    next = Some((time,i));
+                }
+            }
+        }
+        next.map(|(time,i)| {
+            let index = self.positions[i].0;
+            self.positions[i] = (index+1,time);
+            (time,i,&self.tracks[i].events[index].event)
+        })
+    }
+}
+
 /// A standard midi file
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, Clone)]
 pub struct SMF {
     /// The format of the SMF
@@ -253,8 +944,54 @@ pub struct SMF {
     pub division: i16,
 }
 
+#[cfg(feature = "std")]
+impl<'a> TryFrom<&'a Path> for SMF {
+    type Error = SMFError;
+
+    /// Read an SMF file at the given path, see `SMF::from_file`
+    fn try_from(path: &'a Path) -> Result<SMF,SMFError> {
+        SMF::from_file(path)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<'a,'r> TryFrom<&'a mut (dyn Read + 'r)> for SMF {
+    type Error = SMFError;
+
+    /// Read an SMF from the given reader, see `SMF::from_reader`
+    fn try_from(reader: &'a mut (dyn Read + 'r)) -> Result<SMF,SMFError> {
+        SMF::from_reader(reader)
+    }
+}
+
+#[cfg(feature = "std")]
+impl fmt::Display for SMF {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SMF, format: {}, division: {}, tracks: {}",
+               self.format,
+               self.division_parsed(),
+               self.tracks.len())
+    }
+}
 
+#[cfg(feature = "std")]
 impl SMF {
+    /// Return the division of this SMF decoded into either ticks
+    /// per beat or SMPTE units
+    pub fn division_parsed(&self) -> Division {
+        Division::from_i16(self.division)
+    }
+
+    /// Return the number of ticks per quarter note of this SMF, or
+    /// None if the division is in SMPTE units (or is 0)
+    pub fn ticks_per_quarter(&self) -> Option<u16> {
+        if self.division > 0 {
+            Some(self.division as u16)
+        } else {
+            None
+        }
+    }
+
     /// Read an SMF file at the given path
     pub fn from_file(path: &Path) -> Result<SMF,SMFError> {
         let mut file = File::open(path)?;
@@ -266,6 +1003,179 @@ impl SMF {
         SMFReader::read_smf(reader)
     }
 
+    /// Read an SMF from a slice of bytes, e.g. a file that has been
+    /// loaded into memory
+    pub fn from_bytes(bytes: &[u8]) -> Result<SMF,SMFError> {
+        SMFReader::read_smf(&mut Cursor::new(bytes))
+    }
+
+    /// Write this SMF into a new in-memory buffer.  Fails like
+    /// `SMFWriter::try_from_smf` if a delta time or length is too
+    /// big to be written.
+    pub fn to_vec(&self) -> Result<Vec<u8>,Error> {
+        SMFWriter::try_from_smf(self)?.to_vec()
+    }
+
+    /// Change the division of this SMF to `new_division` ticks per
+    /// quarter note, scaling the time of every event to match.
+    /// Times are rounded to the nearest tick.
+    ///
+    /// Fails, leaving the SMF unchanged, if the division is in SMPTE
+    /// units, or if `new_division` is 0 or too large for an SMF (over
+    /// 0x7FFF).
+    pub fn rescale_division(&mut self, new_division: u16) -> Result<(),RescaleError> {
+        let old_division = match self.ticks_per_quarter() {
+            Some(ticks) => ticks as u128,
+            None => return Err(RescaleError::SmpteDivision),
+        };
+        if new_division == 0 || new_division > 0x7FFF {
+            return Err(RescaleError::InvalidDivision(new_division));
+        }
+        let scale = |time: u64| ((time as u128 * new_division as u128 + old_division / 2) / old_division) as u64;
+        for track in self.tracks.iter_mut() {
+            let mut time = 0;
+            let mut new_time = 0;
+            for event in track.events.iter_mut() {
+                time += event.vtime;
+                let scaled = scale(time);
+                event.vtime = scaled - new_time;
+                new_time = scaled;
+            }
+        }
+        self.division = new_division as i16;
+        Ok(())
+    }
+
+    /// Remove the time before the first midi event of any track, so
+    /// the music starts right away.  Every event is moved earlier by
+    /// that amount, keeping their relative timing, except that meta
+    /// events before the first midi event (like tempo and time
+    /// signature setup) end up at time 0.  Returns the number of
+    /// ticks removed.
+    pub fn trim_leading_silence(&mut self) -> u64 {
+        let offset = self.tracks.iter().filter_map(|track| {
+            track.iter_absolute().find(|&(_,event)| match event.event {
+                Event::Midi(_) => true,
+                Event::Meta(_) => false,
+            }).map(|(time,_)| time)
+        }).min().unwrap_or(0);
+        if offset == 0 {
+            return 0;
+        }
+        for track in self.tracks.iter_mut() {
+            let mut time = 0;
+            let mut new_time = 0;
+            for event in track.events.iter_mut() {
+                time += event.vtime;
+                let trimmed = time.saturating_sub(offset);
+                event.vtime = trimmed - new_time;
+                new_time = trimmed;
+            }
+        }
+        offset
+    }
+
+    /// Build a map of the tempo changes in this SMF, which can be
+    /// used to convert tick times into real time.  The tempo changes
+    /// are taken from the first track.
+    /// Returns None if the division is in SMPTE units
+    pub fn build_tempo_map(&self) -> Option<TempoMap> {
+        self.ticks_per_quarter().map(|ticks| {
+            let events = match self.tracks.first() {
+                Some(track) => &track.events[..],
+                None => &[],
+            };
+            TempoMap::from_events(events,ticks)
+        })
+    }
+
+    /// Build a map of the time signature changes in this SMF, which
+    /// can be used to convert tick times into bar:beat:tick
+    /// positions.  The time signatures are taken from the first track.
+    /// Returns None if the division is in SMPTE units
+    pub fn position_map(&self) -> Option<PositionMap> {
+        self.ticks_per_quarter().map(|ticks| {
+            let events = match self.tracks.first() {
+                Some(track) => &track.events[..],
+                None => &[],
+            };
+            PositionMap::from_events(events,ticks)
+        })
+    }
+
+    /// Return the length of this SMF in ticks, i.e. the length of
+    /// its longest track
+    pub fn duration_ticks(&self) -> u64 {
+        self.tracks.iter().map(|t| t.duration_ticks()).max().unwrap_or(0)
+    }
+
+    /// Append `other` to the end of this SMF, so it plays after this
+    /// one.  Each track of `other` is added to the end of the track
+    /// with the same index here, with every event moved later by the
+    /// duration of this SMF.  The EndOfTrack event of a track that is
+    /// added to is removed.  Tracks that this SMF doesn't have yet are
+    /// added.
+    ///
+    /// Fails if the SMFs have different formats or divisions
+    pub fn append(&mut self, other: &SMF) -> Result<(),AppendError> {
+        if self.format != other.format {
+            return Err(AppendError::FormatMismatch);
+        }
+        if self.division != other.division {
+            return Err(AppendError::DivisionMismatch);
+        }
+        let offset = self.duration_ticks();
+        for (i,other_track) in other.tracks.iter().enumerate() {
+            if i >= self.tracks.len() {
+                self.tracks.push(Track {
+                    copyright: other_track.copyright.clone(),
+                    name: other_track.name.clone(),
+                    instrument: other_track.instrument.clone(),
+                    events: Vec::new(),
+                });
+            }
+            let track = &mut self.tracks[i];
+            let mut end = track.duration_ticks();
+            let ends_with_eot = match track.events.last() {
+                Some(&TrackEvent { event: Event::Meta(ref meta), .. }) => meta.command == MetaCommand::EndOfTrack,
+                _ => false,
+            };
+            if ends_with_eot {
+                end -= track.events.pop().unwrap().vtime;
+            }
+            let mut events = other_track.events.iter().cloned();
+            if let Some(mut first) = events.next() {
+                first.vtime += offset - end;
+                track.events.push(first);
+            }
+            track.events.extend(events);
+        }
+        Ok(())
+    }
+
+    /// Check this SMF for common problems, returning a warning for
+    /// each one found.  See `ValidationWarning` for what is checked.
+    pub fn validate(&self) -> Vec<ValidationWarning> {
+        validate::validate(self)
+    }
+
+    /// Compare this SMF to `other`, returning an entry for each
+    /// difference between them.  See `DiffEntry` for what is compared.
+    pub fn diff(&self, other: &SMF) -> Vec<DiffEntry> {
+        diff::diff(self,other)
+    }
+
+    /// Iterate over the events of all tracks merged into a single
+    /// stream ordered by time.  Each item is the absolute time of
+    /// the event in ticks, the index of the track it is from, and
+    /// the event.  Events at the same time are ordered by track index.
+    pub fn iter_merged(&self) -> impl Iterator<Item=(u64,usize,&Event)> {
+        MergedEvents {
+            tracks: &self.tracks,
+            positions: vec![(0,0); self.tracks.len()],
+        }
+    }
+
     /// Convert a type 0 (single track) to type 1 (multi track) SMF
     /// Does nothing if the SMF is already in type 1
     /// Returns None if the SMF is in type 2 (multi song)
@@ -309,7 +1219,7 @@ impl SMF {
                             event.vtime -= time;
                             time = tmp;
                         }
-                        out.tracks.push(Track {events: events.clone(), copyright: None, name: None});
+                        out.tracks.push(Track {events: events.clone(), copyright: None, name: None, instrument: None});
                     }
                 }
                 out.tracks[0].name = self.tracks[0].name.clone();
@@ -318,5 +1228,865 @@ impl SMF {
             }
         }
     }
+
+    /// Convert a type 1 (multi track) to type 0 (single track) SMF by
+    /// merging all the tracks.  Events at the same time are ordered
+    /// with meta events first, as in the SMFBuilder.  The name and
+    /// copyright are taken from the first track.
+    /// Does nothing if the SMF is already in type 0
+    /// Returns None if the SMF is in type 2 (multi song)
+    pub fn to_single_track(&self) -> Option<SMF> {
+        match self.format {
+            SMFFormat::Single => Some(self.clone()),
+            SMFFormat::MultiSong => None,
+            SMFFormat::MultiTrack => {
+                let (copyright,name,instrument) = match self.tracks.first() {
+                    Some(track) => (track.copyright.clone(), track.name.clone(), track.instrument.clone()),
+                    None => (None, None, None),
+                };
+                Some(SMF {
+                    format: SMFFormat::Single,
+                    tracks: vec![Track {
+                        copyright,
+                        name,
+                        instrument,
+                        events: merge_events(self.iter_merged().map(|(time,_,event)| (time,event))),
+                    }],
+                    division: self.division,
+                })
+            }
+        }
+    }
+}
+
+// Sort key for an event at absolute time `time`, putting meta events
+// before midi events at the same time.  Used with a stable sort, so
+// events keep their order otherwise.
+#[cfg(feature = "std")]
+fn event_order(time: u64, event: &Event) -> (u64,u8) {
+    (time, match *event {
+        Event::Meta(_) => 0,
+        Event::Midi(_) => 1,
+    })
+}
+
+// Build the events of a single track from events with absolute
+// times.  Events are ordered by time, and events at the same time
+// are ordered with meta events first (keeping their order
+// otherwise).  Any EndOfTrack events are replaced by a single one at
+// the time of the last event.
+#[cfg(feature = "std")]
+fn merge_events<'a,I>(events: I) -> Vec<TrackEvent> where I: Iterator<Item=(u64,&'a Event)> {
+    let mut end = 0;
+    let mut merged: Vec<(u64,&Event)> = Vec::new();
+    for (time,event) in events {
+        end = end.max(time);
+        match *event {
+            Event::Meta(ref meta) if meta.command == MetaCommand::EndOfTrack => {}
+            _ => merged.push((time,event)),
+        }
+    }
+    merged.sort_by_key(|&(time,event)| event_order(time,event));
+
+    let mut res = Vec::with_capacity(merged.len() + 1);
+    let mut prev_time = 0;
+    for (time,event) in merged {
+        res.push(TrackEvent {
+            vtime: time - prev_time,
+            event: event.clone(),
+        });
+        prev_time = time;
+    }
+    res.push(TrackEvent {
+        vtime: end - prev_time,
+        event: Event::Meta(MetaEvent::end_of_track()),
+    });
+    res
+}
+
+// A track holding `events`, for the tests below
+#[cfg(all(test, feature = "std"))]
+fn track_of(events: &[AbsoluteEvent]) -> Track {
+    let mut builder = SMFBuilder::new();
+    builder.add_static_track(events.iter());
+    builder.result().tracks.remove(0)
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn smpte_division() {
+    use std::io::Cursor;
+
+    // 30 drop frame (29.97 fps), 40 ticks per frame
+    let raw = 0xE328u16 as i16;
+    let division = Division::Smpte { fps: 29, ticks_per_frame: 40 };
+    assert_eq!(Division::from_i16(raw),division);
+    assert_eq!(division.to_i16(),raw);
+    assert_eq!(Division::from_i16(480),Division::TicksPerBeat(480));
+    assert_eq!(Division::TicksPerBeat(480).to_i16(),480);
+
+    let mut bytes = Vec::new();
+    SMFWriter::from_division(division).write_all(&mut bytes).unwrap();
+    let smf = SMF::from_reader(&mut Cursor::new(bytes)).unwrap();
+    assert_eq!(smf.division,raw);
+    assert_eq!(smf.division_parsed(),division);
+    assert_eq!(smf.ticks_per_quarter(),None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn ticks_per_quarter() {
+    let mut smf = SMF { format: SMFFormat::MultiTrack, tracks: Vec::new(), division: 480 };
+    assert_eq!(smf.ticks_per_quarter(),Some(480));
+    smf.division = 0x7FFF;
+    assert_eq!(smf.ticks_per_quarter(),Some(0x7FFF));
+    smf.division = Division::Smpte { fps: 25, ticks_per_frame: 40 }.to_i16();
+    assert_eq!(smf.ticks_per_quarter(),None);
+    smf.division = 0;
+    assert_eq!(smf.ticks_per_quarter(),None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn merged_iteration() {
+    let mut builder = SMFBuilder::new();
+    for _ in 0..3 {
+        builder.add_track();
+    }
+    builder.add_midi_abs(0,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(0,20,MidiMessage::note_off(60,0,0)).unwrap();
+    builder.add_midi_abs(1,10,MidiMessage::note_on(62,100,1)).unwrap();
+    builder.add_midi_abs(1,20,MidiMessage::note_off(62,0,1)).unwrap();
+    builder.add_midi_abs(2,5,MidiMessage::note_on(64,100,2)).unwrap();
+    builder.add_midi_abs(2,30,MidiMessage::note_off(64,0,2)).unwrap();
+    let smf = builder.result();
+
+    let merged: Vec<(u64,usize,u8)> = smf.iter_merged().map(|(time,track,event)| {
+        match *event {
+            Event::Midi(ref m) => (time,track,m.data(1)),
+            Event::Meta(_) => panic!("unexpected meta event"),
+        }
+    }).collect();
+    assert_eq!(merged,vec![(0,0,60),(5,2,64),(10,1,62),(20,0,60),(20,1,62),(30,2,64)]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn sysex_len() {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_midi_abs(0,0,MidiMessage::from_bytes(vec![0xF0,0x7E,0x7F,0x09,0x01,0xF7])).unwrap();
+    builder.add_midi_abs(0,200,MidiMessage::from_bytes(vec![0xF7,0x01,0x02])).unwrap();
+    builder.add_midi_abs(0,300,MidiMessage::note_on(60,100,0)).unwrap();
+
+    let mut bytes = Vec::new();
+    SMFWriter::from_smf(builder.result()).write_all(&mut bytes).unwrap();
+    let declared = ((bytes[18] as usize) << 24) | ((bytes[19] as usize) << 16) |
+                   ((bytes[20] as usize) << 8) | bytes[21] as usize;
+
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    let events = &smf.tracks[0].events;
+    assert_eq!(events[0].event.len(),7);
+    assert_eq!(events[1].len(),6);
+    assert_eq!(events.iter().map(|e| e.len()).sum::<usize>(),declared);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn from_bytes() {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&[0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96]);
+    bytes.extend_from_slice(&[0x4D,0x54,0x72,0x6B,0,0,0,12,
+                              0x00,0x90,0x3C,0x64,
+                              0x60,0x80,0x3C,0x00,
+                              0x00,0xFF,0x2F,0x00]);
+    let smf = SMF::from_bytes(&bytes).unwrap();
+    assert_eq!(smf.format,SMFFormat::Single);
+    assert_eq!(smf.division,96);
+    assert_eq!(smf.tracks[0].events.len(),3);
+    assert_eq!(smf.duration_ticks(),96);
+
+    assert!(SMF::from_bytes(&bytes[..20]).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn to_vec() {
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,12,
+                 0x00,0x90,0x3C,0x64,
+                 0x60,0x80,0x3C,0x00,
+                 0x00,0xFF,0x2F,0x00];
+    let smf = SMF::from_bytes(&bytes).unwrap();
+    let written = smf.to_vec().unwrap();
+    assert_eq!(written,&bytes[..]);
+    let reread = SMF::from_bytes(&written).unwrap();
+    assert_eq!(reread.tracks.len(),smf.tracks.len());
+    assert_eq!(reread.tracks[0].events.len(),smf.tracks[0].events.len());
+    assert_eq!(SMFWriter::from_smf(reread).to_vec().unwrap(),&bytes[..]);
+
+    let mut too_long = smf.clone();
+    too_long.tracks[0].events[1].vtime = 1 << 28;
+    assert_eq!(too_long.to_vec().unwrap_err().kind(),::std::io::ErrorKind::InvalidInput);
+    assert!(SMFWriter::from_smf(too_long).to_vec().is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn display_smf() {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_track();
+    let mut smf = builder.result();
+    smf.division = 480;
+    assert_eq!(format!("{}",smf),"SMF, format: multiple track, division: 480 ticks/beat, tracks: 2");
+
+    smf.division = Division::Smpte { fps: 25, ticks_per_frame: 40 }.to_i16();
+    assert_eq!(format!("{}",smf),"SMF, format: multiple track, division: SMPTE 25 fps, 40 ticks/frame, tracks: 2");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn single_track() {
+    let mut builder = SMFBuilder::new();
+    for _ in 0..3 {
+        builder.add_track();
+    }
+    builder.add_meta_abs(0,0,MetaEvent::tempo_setting(500000)).unwrap();
+    builder.add_meta_abs(0,96,MetaEvent::marker_text("B".to_string())).unwrap();
+    builder.add_midi_abs(1,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(1,96,MidiMessage::note_off(60,0,0)).unwrap();
+    builder.add_midi_abs(2,48,MidiMessage::note_on(64,100,1)).unwrap();
+    builder.add_midi_abs(2,96,MidiMessage::note_off(64,0,1)).unwrap();
+    let mut smf = builder.result();
+    smf.division = 96;
+
+    let single = smf.to_single_track().unwrap();
+    assert_eq!(single.format,SMFFormat::Single);
+    assert_eq!(single.tracks.len(),1);
+    let events: Vec<(u64,String)> = single.tracks[0].events.iter().map(|e| (e.vtime,format!("{}",e.event))).collect();
+    let expected: Vec<(u64,String)> = vec![
+        (0,Event::Meta(MetaEvent::tempo_setting(500000))),
+        (0,Event::Midi(MidiMessage::note_on(60,100,0))),
+        (48,Event::Midi(MidiMessage::note_on(64,100,1))),
+        (48,Event::Meta(MetaEvent::marker_text("B".to_string()))),
+        (0,Event::Midi(MidiMessage::note_off(60,0,0))),
+        (0,Event::Midi(MidiMessage::note_off(64,0,1))),
+        (0,Event::Meta(MetaEvent::end_of_track())),
+    ].into_iter().map(|(vtime,e)| (vtime,format!("{}",e))).collect();
+    assert_eq!(events,expected);
+
+    let mut bytes = Vec::new();
+    SMFWriter::from_smf(single).write_all(&mut bytes).unwrap();
+    let read = SMF::from_reader(&mut &bytes[..]).unwrap();
+    assert_eq!(read.format,SMFFormat::Single);
+    assert_eq!(read.tracks[0].events.len(),7);
+
+    smf.format = SMFFormat::MultiSong;
+    assert!(smf.to_single_track().is_none());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_round_trip() {
+    extern crate serde_json;
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.set_name(0,"piano".to_string()).unwrap();
+    builder.add_midi_abs(0,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_meta_abs(0,96,MetaEvent::raw(0x60,vec![1,2])).unwrap();
+    builder.add_midi_abs(0,96,MidiMessage::note_off(60,0,0)).unwrap();
+    let smf = builder.result();
+
+    let json = serde_json::to_string(&smf).unwrap();
+    assert!(json.contains("\"data\":[144,60,100]"));
+    let back: SMF = serde_json::from_str(&json).unwrap();
+    assert_eq!(back.format,smf.format);
+    assert_eq!(back.tracks.len(),1);
+    assert_eq!(back.tracks[0].name,Some("piano".to_string()));
+    assert_eq!(format!("{:?}",back.tracks[0].events),format!("{:?}",smf.tracks[0].events));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn duration_ticks() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(480,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_meta(960,MetaEvent::end_of_track()),
+    ];
+    let mut builder = SMFBuilder::new();
+    builder.add_static_track(events.iter());
+    builder.add_track();
+    builder.add_midi_abs(1,200,MidiMessage::note_on(64,100,0)).unwrap();
+    let smf = builder.result();
+    assert_eq!(smf.tracks[0].duration_ticks(),960);
+    assert_eq!(smf.tracks[1].duration_ticks(),200);
+    assert_eq!(smf.duration_ticks(),960);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn transpose() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(120,100,0)),
+        AbsoluteEvent::new_midi(20,MidiMessage::control_change(7,100,0)),
+        AbsoluteEvent::new_midi(30,MidiMessage::note_off(60,0,0)),
+    ];
+    let track = track_of(&events);
+    let data = |track: &Track| -> Vec<(u64,Vec<u8>)> {
+        track.events.iter().map(|e| match e.event {
+            Event::Midi(ref m) => (e.vtime,m.data.clone()),
+            Event::Meta(_) => (e.vtime,vec![]),
+        }).collect()
+    };
+
+    let mut clamped = track.clone();
+    clamped.transpose(12,OutOfRange::Clamp);
+    assert_eq!(data(&clamped),vec![(0,vec![0x90,72,100]),
+                                   (10,vec![0x90,127,100]),
+                                   (10,vec![0xB0,7,100]),
+                                   (10,vec![0x80,72,0])]);
+
+    let mut skipped = track.clone();
+    skipped.transpose(12,OutOfRange::Skip);
+    assert_eq!(data(&skipped),vec![(0,vec![0x90,72,100]),
+                                   (20,vec![0xB0,7,100]),
+                                   (10,vec![0x80,72,0])]);
+
+    let mut down = track;
+    down.transpose(-64,OutOfRange::Clamp);
+    assert_eq!(down.events[0].event.to_string(),MidiMessage::note_on(0,100,0).to_string());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn retain_events() {
+    let events = [
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(25,MidiMessage::control_change(7,100,0)),
+        AbsoluteEvent::new_midi(40,MidiMessage::note_off(60,0,0)),
+    ];
+    let mut track = track_of(&events);
+    track.retain_events(|e| match e.event {
+        Event::Midi(ref m) => m.controller().is_none(),
+        Event::Meta(_) => true,
+    });
+    assert_eq!(track.events.len(),2);
+    assert_eq!(track.events[0].vtime,10);
+    assert_eq!(track.events[1].vtime,30);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn merge_tracks() {
+    let notes = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(100,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_meta(100,MetaEvent::end_of_track()),
+    ];
+    let controls = [
+        AbsoluteEvent::new_midi(50,MidiMessage::control_change(7,80,0)),
+        AbsoluteEvent::new_meta(100,MetaEvent::marker_text("end".to_string())),
+        AbsoluteEvent::new_midi(150,MidiMessage::control_change(7,100,0)),
+        AbsoluteEvent::new_meta(200,MetaEvent::end_of_track()),
+    ];
+    let mut builder = SMFBuilder::new();
+    builder.add_static_track(notes.iter());
+    builder.add_static_track(controls.iter());
+    builder.set_name(0,"notes".to_string()).unwrap();
+    let smf = builder.result();
+
+    let merged = smf.tracks[0].merge(&smf.tracks[1]);
+    assert_eq!(merged.name,Some("notes".to_string()));
+    let events: Vec<String> = merged.events.iter().map(|e| e.to_string()).collect();
+    let expected: Vec<String> = [
+        TrackEvent { vtime: 0, event: Event::Meta(MetaEvent::sequence_or_track_name("notes".to_string())) },
+        TrackEvent { vtime: 0, event: Event::Midi(MidiMessage::note_on(60,100,0)) },
+        TrackEvent { vtime: 50, event: Event::Midi(MidiMessage::control_change(7,80,0)) },
+        TrackEvent { vtime: 50, event: Event::Meta(MetaEvent::marker_text("end".to_string())) },
+        TrackEvent { vtime: 0, event: Event::Midi(MidiMessage::note_off(60,0,0)) },
+        TrackEvent { vtime: 50, event: Event::Midi(MidiMessage::control_change(7,100,0)) },
+        TrackEvent { vtime: 50, event: Event::Meta(MetaEvent::end_of_track()) },
+    ].iter().map(|e| e.to_string()).collect();
+    assert_eq!(events,expected);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn track_notes() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(60,80,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(60,90,1)),
+        AbsoluteEvent::new_midi(20,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_midi(25,MidiMessage::note_on(64,70,0)),
+        AbsoluteEvent::new_midi(30,MidiMessage::note_on(60,0,0)),
+        AbsoluteEvent::new_midi(35,MidiMessage::note_off(60,0,1)),
+        AbsoluteEvent::new_meta(50,MetaEvent::end_of_track()),
+    ];
+    let notes = track_of(&events).notes();
+    let note = |channel,pitch,velocity,start_tick,duration_ticks| Note { channel, pitch, velocity, start_tick, duration_ticks };
+    assert_eq!(notes,vec![note(0,60,100,0,20),
+                          note(0,60,80,10,20),
+                          note(1,60,90,10,25),
+                          note(0,64,70,25,25)]);
 }
 
+#[cfg(feature = "std")]
+#[test]
+fn try_from() {
+    use std::env;
+    use std::fs;
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap().note_off_at(0,96,60,0,0).unwrap();
+    let mut bytes = Vec::new();
+    SMFWriter::from(builder.result()).write_all(&mut bytes).unwrap();
+
+    let path = env::temp_dir().join(format!("rimd-try-from-{}.mid",std::process::id()));
+    fs::write(&path,&bytes).unwrap();
+    let from_path = SMF::try_from(path.as_path());
+    fs::remove_file(&path).unwrap();
+    let from_path = from_path.unwrap();
+    assert_eq!(from_path.tracks[0].events.len(),3);
+
+    let reader: &mut dyn Read = &mut &bytes[..];
+    let from_reader = SMF::try_from(reader).unwrap();
+    assert_eq!(from_reader.division,from_path.division);
+
+    assert!(SMF::try_from(Path::new("/nonexistent/rimd.mid")).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn events_on_channel() {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap()
+        .note_on_at(0,0,64,100,1).unwrap()
+        .control_change_at(0,5,7,90,1).unwrap()
+        .meta_at(0,8,MetaEvent::marker_text("A".to_string())).unwrap()
+        .note_off_at(0,10,60,0,0).unwrap()
+        .note_off_at(0,10,64,0,1).unwrap();
+    let track = &builder.result().tracks[0];
+
+    let data = |channel| -> Vec<Vec<u8>> {
+        track.events_on_channel(channel).map(|e| match e.event {
+            Event::Midi(ref m) => m.data.clone(),
+            Event::Meta(_) => panic!("meta event on channel"),
+        }).collect()
+    };
+    assert_eq!(data(0),vec![vec![0x90,60,100],vec![0x80,60,0]]);
+    assert_eq!(data(1),vec![vec![0x91,64,100],vec![0xB1,7,90],vec![0x81,64,0]]);
+    assert!(data(2).is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn remap_channels() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::program_change(5,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(36,100,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,1)),
+        AbsoluteEvent::new_midi(0,MidiMessage::sysex(&[0x7E,0x7F,0x09,0x01])),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_off(36,0,0)),
+        AbsoluteEvent::new_meta(10,MetaEvent::end_of_track()),
+    ];
+    let track = track_of(&events);
+    let statuses = |track: &Track| -> Vec<u8> {
+        track.events.iter().filter_map(|e| match e.event {
+            Event::Midi(ref m) => Some(m.data[0]),
+            Event::Meta(_) => None,
+        }).collect()
+    };
+
+    let mut drums = track.clone();
+    drums.remap_channel(0,9);
+    assert_eq!(statuses(&drums),vec![0xC9,0x99,0x91,0xF0,0x89]);
+    assert_eq!(drums.events.len(),track.events.len());
+
+    let mut swapped = track;
+    let mut map = [0; 16];
+    for (channel,slot) in map.iter_mut().enumerate() {
+        *slot = 15 - channel as u8;
+    }
+    swapped.remap_all_channels(&map);
+    assert_eq!(statuses(&swapped),vec![0xCF,0x9F,0x9E,0xF0,0x8F]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn channels_and_programs_used() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::program_change(5,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(36,100,9)),
+        AbsoluteEvent::new_midi(10,MidiMessage::program_change(7,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::sysex(&[0x7E,0x7F,0x09,0x01])),
+        AbsoluteEvent::new_meta(20,MetaEvent::end_of_track()),
+    ];
+    let track = track_of(&events);
+
+    let channels = track.channels_used();
+    assert_eq!(channels.len(),2);
+    assert!(channels.contains(&0) && channels.contains(&9));
+
+    let programs = track.programs_used();
+    assert_eq!(programs.len(),1);
+    assert_eq!(programs.get(&0),Some(&7));
+
+    let empty = Track { copyright: None, name: None, instrument: None, events: Vec::new() };
+    assert!(empty.channels_used().is_empty());
+    assert!(empty.programs_used().is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn iter_absolute() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(64,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_meta(25,MetaEvent::end_of_track()),
+    ];
+    let track = track_of(&events);
+
+    let times: Vec<u64> = track.iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![0,10,10,25]);
+    let mut time = 0;
+    for (abs,event) in track.iter_absolute() {
+        time += event.vtime;
+        assert_eq!(abs,time);
+    }
+    assert_eq!(track.iter_absolute().last().map(|(time,_)| time),Some(track.duration_ticks()));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn humanize() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(96,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_midi(96,MidiMessage::note_on(62,127,0)),
+        AbsoluteEvent::new_midi(192,MidiMessage::note_on(62,0,0)),
+        AbsoluteEvent::new_midi(192,MidiMessage::note_on(64,1,0)),
+        AbsoluteEvent::new_midi(288,MidiMessage::note_off(64,0,0)),
+        AbsoluteEvent::new_meta(300,MetaEvent::end_of_track()),
+    ];
+    let track = track_of(&events);
+
+    let humanized = |seed| {
+        let mut track = track.clone();
+        track.humanize(10,20,seed);
+        track
+    };
+    let strings = |track: &Track| track.events.iter().map(|event| event.to_string()).collect::<Vec<String>>();
+    let first = humanized(42);
+    assert_eq!(strings(&first),strings(&humanized(42)));
+    assert_ne!(strings(&first),strings(&humanized(43)));
+
+    assert_eq!(first.events.len(),track.events.len());
+    assert!(first.duration_ticks() <= track.duration_ticks() + 10);
+    assert!(first.events.last().unwrap().to_string().contains("End Of Track"));
+    let notes = first.notes();
+    assert_eq!(notes.len(),3);
+    for note in notes.iter() {
+        assert_eq!(note.duration_ticks,96);
+        assert!((1..=127).contains(&note.velocity));
+    }
+
+    // no spread leaves the track alone
+    let mut same = track.clone();
+    same.humanize(0,0,42);
+    assert_eq!(strings(&same),strings(&track));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn event_accessors() {
+    let mut midi = Event::Midi(MidiMessage::note_on(60,100,0));
+    let mut meta = Event::Meta(MetaEvent::end_of_track());
+    assert!(midi.is_midi() && !midi.is_meta());
+    assert!(meta.is_meta() && !meta.is_midi());
+
+    assert_eq!(midi.as_midi(),Some(&MidiMessage::note_on(60,100,0)));
+    assert!(midi.as_meta().is_none());
+    assert_eq!(meta.as_meta().map(|meta| meta.command),Some(MetaCommand::EndOfTrack));
+    assert!(meta.as_midi().is_none());
+
+    midi.as_midi_mut().unwrap().data[2] = 64;
+    assert_eq!(midi,Event::Midi(MidiMessage::note_on(60,64,0)));
+    assert!(midi.as_meta_mut().is_none());
+    meta.as_meta_mut().unwrap().command = MetaCommand::CuePoint;
+    assert_eq!(meta.as_meta().unwrap().command,MetaCommand::CuePoint);
+    assert!(meta.as_midi_mut().is_none());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn hash_events() {
+    let messages: HashSet<MidiMessage> = [
+        MidiMessage::note_on(60,100,0),
+        MidiMessage::note_on(60,100,0),
+    ].iter().cloned().collect();
+    assert_eq!(messages.len(),1);
+
+    let metas: HashSet<MetaEvent> = vec![MetaEvent::tempo_setting(500000),MetaEvent::tempo_setting(500000)].into_iter().collect();
+    assert_eq!(metas.len(),1);
+
+    let events: HashSet<Event> = [
+        Event::Midi(MidiMessage::note_on(60,100,0)),
+        Event::Midi(MidiMessage::note_on(60,100,0)),
+        Event::Midi(MidiMessage::note_on(60,100,1)),
+        Event::Meta(MetaEvent::end_of_track()),
+        Event::Meta(MetaEvent::end_of_track()),
+    ].iter().cloned().collect();
+    assert_eq!(events.len(),3);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn insert_at() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(96,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_midi(192,MidiMessage::note_on(62,100,0)),
+        AbsoluteEvent::new_midi(288,MidiMessage::note_off(62,0,0)),
+        AbsoluteEvent::new_meta(300,MetaEvent::end_of_track()),
+    ];
+    let mut track = track_of(&events);
+
+    let cc = MidiMessage::control_change(1,64,0);
+    track.insert_at(150,Event::Midi(cc.clone()));
+    let times: Vec<u64> = track.iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![0,96,150,192,288,300]);
+    assert_eq!(track.events[2].event,Event::Midi(cc.clone()));
+    assert_eq!(track.events[2].vtime,54);
+    assert_eq!(track.events[3].vtime,42);
+
+    // goes after events at the same time
+    track.insert_at(96,Event::Midi(cc.clone()));
+    assert_eq!(track.events[2].event,Event::Midi(cc.clone()));
+    assert_eq!(track.events[2].vtime,0);
+
+    // past the end moves the EndOfTrack
+    track.insert_at(400,Event::Midi(cc.clone()));
+    let times: Vec<u64> = track.iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![0,96,96,150,192,288,400,400]);
+    assert_eq!(track.events[6].event,Event::Midi(cc));
+    assert!(track.events[7].event.is_meta());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn remove_event() {
+    let events = [
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(25,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_meta(40,MetaEvent::end_of_track()),
+    ];
+    let mut track = track_of(&events);
+
+    let removed = track.remove(0).unwrap();
+    assert_eq!(removed.event,Event::Midi(MidiMessage::note_on(60,100,0)));
+    assert_eq!(track.events[0].vtime,25);
+    let times: Vec<u64> = track.iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![25,40]);
+
+    assert!(track.remove(2).is_none());
+    assert!(track.remove(1).is_some());
+    assert_eq!(track.duration_ticks(),25);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn map_velocity() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(64,1,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(60,0,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_off(64,80,0)),
+    ];
+    let mut track = track_of(&events);
+
+    track.map_velocity(|velocity| velocity / 2);
+    let velocities: Vec<Option<u8>> = track.events.iter().map(|event| event.event.as_midi().and_then(|msg| msg.velocity())).collect();
+    assert_eq!(velocities,vec![Some(50),Some(1),Some(0),Some(80)]);
+
+    track.map_velocity(|velocity| velocity.saturating_mul(4));
+    assert_eq!(track.events[0].event,Event::Midi(MidiMessage::note_on(60,127,0)));
+    assert_eq!(track.events[2].event,Event::Midi(MidiMessage::note_on(60,0,0)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn events_in_range() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(64,100,0)),
+        AbsoluteEvent::new_midi(20,MidiMessage::note_off(60,0,0)),
+        AbsoluteEvent::new_midi(30,MidiMessage::note_off(64,0,0)),
+        AbsoluteEvent::new_meta(40,MetaEvent::end_of_track()),
+    ];
+    let track = track_of(&events);
+
+    let window = track.events_in_range(10,30);
+    let times: Vec<u64> = window.iter().map(|&(time,_)| time).collect();
+    assert_eq!(times,vec![10,20]);
+    assert_eq!(window[0].1.to_string(),track.events[1].to_string());
+    assert!(track.events_in_range(41,100).is_empty());
+    assert_eq!(track.events_in_range(0,u64::MAX).len(),5);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn dedup_consecutive() {
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::control_change(7,100,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::control_change(7,100,0)),
+        AbsoluteEvent::new_midi(0,MidiMessage::control_change(7,100,1)),
+        AbsoluteEvent::new_midi(10,MidiMessage::control_change(7,100,1)),
+        AbsoluteEvent::new_midi(10,MidiMessage::control_change(7,90,1)),
+        AbsoluteEvent::new_meta(20,MetaEvent::end_of_track()),
+    ];
+    let mut track = track_of(&events);
+    track.dedup_consecutive();
+
+    let data: Vec<(u64,Vec<u8>)> = track.iter_absolute().map(|(time,e)| match e.event {
+        Event::Midi(ref m) => (time,m.data.clone()),
+        Event::Meta(_) => (time,vec![]),
+    }).collect();
+    assert_eq!(data,vec![(0,vec![0xB0,7,100]),
+                         (0,vec![0xB1,7,100]),
+                         (10,vec![0xB1,7,100]),
+                         (10,vec![0xB1,7,90]),
+                         (20,vec![])]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn lyrics_and_markers() {
+    let events = [
+        AbsoluteEvent::new_meta(0,MetaEvent::marker_text("Verse".to_string())),
+        AbsoluteEvent::new_meta(0,MetaEvent::lyric_text("Hel".to_string())),
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_meta(48,MetaEvent::lyric_text("lo".to_string())),
+        AbsoluteEvent::new_meta(96,MetaEvent::text_event("not a lyric".to_string())),
+        AbsoluteEvent::new_meta(96,MetaEvent::end_of_track()),
+    ];
+    let track = track_of(&events);
+
+    assert_eq!(track.lyrics(),vec![(0,"Hel".to_string()),(48,"lo".to_string())]);
+    assert_eq!(track.markers(),vec![(0,"Verse".to_string())]);
+
+    // a lyric of "日本" in Shift-JIS
+    use encoding::all::WINDOWS_31J;
+    let lyric = MetaEvent::lyric_text_encoded("日本",WINDOWS_31J);
+    assert_eq!(lyric.data,vec![0x93,0xFA,0x96,0x7B]);
+    assert_eq!(lyric.text(WINDOWS_31J),"日本");
+    let track = track_of(&[AbsoluteEvent::new_meta(24,lyric)]);
+    assert_eq!(track.lyrics_with(WINDOWS_31J),vec![(24,"日本".to_string())]);
+    assert_eq!(track.markers_with(WINDOWS_31J),vec![]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn rescale_division() {
+    let mut builder = SMFBuilder::new();
+    builder.set_division(96);
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap()
+        .note_off_at(0,96,60,0,0).unwrap()
+        .note_on_at(0,97,62,100,0).unwrap()
+        .note_off_at(0,144,62,0,0).unwrap();
+    let mut smf = builder.result();
+
+    smf.rescale_division(480).unwrap();
+    assert_eq!(smf.division,480);
+    let times: Vec<u64> = smf.tracks[0].iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![0,480,485,720]);
+
+    // rounds to the nearest tick going down
+    smf.rescale_division(100).unwrap();
+    let times: Vec<u64> = smf.tracks[0].iter_absolute().map(|(time,_)| time).collect();
+    assert_eq!(times,vec![0,100,101,150]);
+
+    assert_eq!(smf.rescale_division(0),Err(RescaleError::InvalidDivision(0)));
+    assert_eq!(smf.rescale_division(0x8000),Err(RescaleError::InvalidDivision(0x8000)));
+    smf.division = Division::Smpte { fps: 25, ticks_per_frame: 40 }.to_i16();
+    assert_eq!(smf.rescale_division(480),Err(RescaleError::SmpteDivision));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn trim_leading_silence() {
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.meta_at(0,0,MetaEvent::tempo_setting(500000)).unwrap()
+        .meta_at(0,480,MetaEvent::time_signature(4,2,24,8)).unwrap()
+        .meta_at(0,1920,MetaEvent::tempo_setting(400000)).unwrap();
+    builder.add_track();
+    builder.note_on_at(1,960,60,100,0).unwrap().note_off_at(1,1440,60,0,0).unwrap();
+    builder.add_track();
+    builder.note_on_at(2,1200,64,100,1).unwrap();
+    let mut smf = builder.result();
+
+    assert_eq!(smf.trim_leading_silence(),960);
+    let times = |track: &Track| -> Vec<u64> { track.iter_absolute().map(|(time,_)| time).collect() };
+    assert_eq!(times(&smf.tracks[0]),vec![0,0,960]);
+    assert_eq!(times(&smf.tracks[1]),vec![0,480]);
+    assert_eq!(times(&smf.tracks[2]),vec![240]);
+
+    // nothing more to trim
+    assert_eq!(smf.trim_leading_silence(),0);
+    assert_eq!(times(&smf.tracks[1]),vec![0,480]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn append() {
+    let song = |note: u8| {
+        let mut builder = SMFBuilder::new();
+        builder.add_track();
+        builder.meta_at(0,0,MetaEvent::tempo_setting(500000)).unwrap()
+            .meta_at(0,960,MetaEvent::end_of_track()).unwrap();
+        builder.add_track();
+        builder.note_on_at(1,0,note,100,0).unwrap()
+            .note_off_at(1,480,note,0,0).unwrap()
+            .meta_at(1,500,MetaEvent::end_of_track()).unwrap();
+        builder.result()
+    };
+    let mut smf = song(60);
+    let mut other = song(64);
+    other.tracks.push(Track { copyright: None, name: Some("extra".to_string()), instrument: None, events: vec![
+        TrackEvent { vtime: 10, event: Event::Meta(MetaEvent::end_of_track()) },
+    ]});
+    smf.append(&other).unwrap();
+
+    assert_eq!(smf.tracks.len(),3);
+    assert_eq!(smf.duration_ticks(),1920);
+    let events = |track: &Track| -> Vec<(u64,String)> {
+        track.iter_absolute().map(|(time,event)| (time,event.event.to_string())).collect()
+    };
+    assert_eq!(events(&smf.tracks[1]),vec![
+        (0,MidiMessage::note_on(60,100,0).to_string()),
+        (480,MidiMessage::note_off(60,0,0).to_string()),
+        (960,MidiMessage::note_on(64,100,0).to_string()),
+        (1440,MidiMessage::note_off(64,0,0).to_string()),
+        (1460,MetaEvent::end_of_track().to_string()),
+    ]);
+    assert_eq!(events(&smf.tracks[0]).len(),3);
+    assert_eq!(events(&smf.tracks[0])[1],(960,MetaEvent::tempo_setting(500000).to_string()));
+    assert_eq!(events(&smf.tracks[2]),vec![(970,MetaEvent::end_of_track().to_string())]);
+    assert_eq!(smf.tracks[2].name,Some("extra".to_string()));
+
+    let mut other = song(64);
+    other.division = 96;
+    assert_eq!(smf.append(&other),Err(AppendError::DivisionMismatch));
+    other.format = SMFFormat::Single;
+    assert_eq!(smf.append(&other),Err(AppendError::FormatMismatch));
+}
@@ -1,18 +1,35 @@
+#[cfg(feature = "std")]
 use std::error;
+#[cfg(feature = "std")]
 use std::io::{Error, Read};
+// there are no io errors without std, so `MetaError::Error` can't occur
+#[cfg(not(feature = "std"))]
+use std::convert::Infallible as Error;
 use std::fmt;
+use std::hash::{Hash,Hasher};
+#[cfg(not(feature = "std"))]
+use alloc::{string::String, vec::Vec};
 
+#[cfg(feature = "std")]
 use reader::SMFReader;
 
+#[cfg(feature = "std")]
+use encoding::{EncoderTrap, EncodingRef};
+#[cfg(feature = "std")]
+use encoding::all::ISO_8859_1;
 use num_traits::FromPrimitive;
 
-use util::{read_byte, read_amount, latin1_decode};
+use util::{decode_vlq_slice, key_name};
+#[cfg(feature = "std")]
+use util::{read_byte, read_amount, decode_text};
 
 /// An error that can occur parsing a meta command
 #[derive(Debug)]
 pub enum MetaError {
     InvalidCommand(u8),
     OtherErr(&'static str),
+    /// An error reading the event.  Without the `std` feature this
+    /// holds `core::convert::Infallible`, so never happens.
     Error(Error),
 }
 
@@ -22,6 +39,7 @@ impl From<Error> for MetaError {
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for MetaError {
     fn description(&self) -> &str {
         match *self {
@@ -50,7 +68,8 @@ impl fmt::Display for MetaError {
 }
 
 /// Commands that meta messages can represent
-#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd,Ord,  FromPrimitive)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd,Ord,  FromPrimitive)]
 pub enum MetaCommand {
     SequenceNumber = 0x00,
     TextEvent = 0x01,
@@ -71,12 +90,41 @@ pub enum MetaCommand {
     Unknown,
 }
 
+/// The mode of a key signature
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+/// The time given by an SMPTEOffset meta event
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SmpteOffset {
+    /// Frames per second: 24, 25, 29 (30 drop frame) or 30
+    pub fps: u8,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    /// Fractional frames, in 100ths of a frame
+    pub fractional: u8,
+}
+
 /// Meta event building and parsing.  See
 /// http://cs.fit.edu/~ryan/cse4051/projects/midi/midi.html#meta_event
 /// for a description of the various meta events and their formats
+///
+/// Note that building a MetaEvent with a struct literal now needs the
+/// `raw_command` field too, which can be set to `command as u8` for
+/// any command but `MetaCommand::Unknown`.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug)]
 pub struct MetaEvent {
     pub command: MetaCommand,
+    /// The command byte of an event whose `command` is
+    /// `MetaCommand::Unknown`.  It's ignored for other commands, see
+    /// `raw_command()` for the byte that's written out.
+    pub raw_command: u8,
     pub length: u64,
     pub data: Vec<u8>,
 }
@@ -85,41 +133,68 @@ impl Clone for MetaEvent {
     fn clone(&self) -> MetaEvent {
         MetaEvent {
             command: self.command,
+            raw_command: self.raw_command,
             length: self.length,
             data: self.data.clone(),
         }
     }
 }
 
+// compare by the command byte that's written out, so events that
+// only differ in an unused `raw_command` are equal
+impl PartialEq for MetaEvent {
+    fn eq(&self, other: &MetaEvent) -> bool {
+        self.command == other.command &&
+            self.raw_command() == other.raw_command() &&
+            self.length == other.length &&
+            self.data == other.data
+    }
+}
+
+impl Eq for MetaEvent {}
+
+impl Hash for MetaEvent {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.command.hash(state);
+        self.raw_command().hash(state);
+        self.length.hash(state);
+        self.data.hash(state);
+    }
+}
+
 impl fmt::Display for MetaEvent {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Meta Event: {}",
                match self.command {
                    MetaCommand::SequenceNumber => format!("Sequence Number: {}", ((self.data[0] as u16) << 8) | self.data[1] as u16),
                    MetaCommand::TextEvent => {
-                       format!("Text Event. Len: {} Text: {}", self.length, latin1_decode(&self.data))
+                       format!("Text Event. Len: {} Text: {}", self.length, self.latin1_text())
                    },
                    MetaCommand::CopyrightNotice => {
-                       format!("Copyright Notice: {}", latin1_decode(&self.data))
+                       format!("Copyright Notice: {}", self.latin1_text())
                    },
                    MetaCommand::SequenceOrTrackName => {
-                       format!("Sequence/Track Name, length: {}, name: {}", self.length, latin1_decode(&self.data))
+                       format!("Sequence/Track Name, length: {}, name: {}", self.length, self.latin1_text())
                    },
                    MetaCommand::InstrumentName => {
-                       format!("InstrumentName: {}", latin1_decode(&self.data))
+                       format!("InstrumentName: {}", self.latin1_text())
                    },
                    MetaCommand::LyricText => {
-                       format!("LyricText: {}", latin1_decode(&self.data))
+                       format!("LyricText: {}", self.latin1_text())
                    }
                    MetaCommand::MarkerText => {
-                       format!("MarkerText: {}", latin1_decode(&self.data))
+                       format!("MarkerText: {}", self.latin1_text())
                    }
-                   MetaCommand::CuePoint => format!("CuePoint: {}", latin1_decode(&self.data)),
+                   MetaCommand::CuePoint => format!("CuePoint: {}", self.latin1_text()),
                    MetaCommand::MIDIChannelPrefixAssignment => format!("MIDI Channel Prefix Assignment, channel: {}", self.data[0]+1),
                    MetaCommand::MIDIPortPrefixAssignment => format!("MIDI Port Prefix Assignment, port: {}", self.data[0]),
                    MetaCommand::EndOfTrack => format!("End Of Track"),
                    MetaCommand::TempoSetting => format!("Set Tempo, microseconds/quarter note: {}", self.data_as_u64(3)),
-                   MetaCommand::SMPTEOffset => format!("SMPTEOffset"),
+                   MetaCommand::SMPTEOffset => match self.smpte_offset_data() {
+                       Some(o) => format!("SMPTEOffset: {:02}:{:02}:{:02}:{:02}.{:02} ({} fps)",
+                                          o.hours, o.minutes, o.seconds, o.frames, o.fractional, o.fps),
+                       None => String::from("SMPTEOffset"),
+                   },
                    MetaCommand::TimeSignature => format!("Time Signature: {}/{}, {} ticks/metronome click, {} 32nd notes/quarter note",
                                                          self.data[0],
                                                          2usize.pow(self.data[1] as u32),
@@ -133,13 +208,54 @@ impl fmt::Display for MetaEvent {
                                                             _ => "Invalid Signature",
                                                         }),
                    MetaCommand::SequencerSpecificEvent => format!("SequencerSpecificEvent"),
-                   MetaCommand::Unknown => format!("Unknown, length: {}", self.data.len()),
+                   MetaCommand::Unknown => format!("Unknown (0x{:02X}), length: {}", self.raw_command(), self.data.len()),
                })
     }
 }
 
+// A tempo of `bpm` quarter notes per minute in microseconds per
+// quarter note, rounded and clamped to what fits in the 24-bit value
+// of a TempoSetting event
+#[cfg(feature = "std")]
+pub fn bpm_to_micros(bpm: f64) -> u32 {
+    let micros = (60_000_000.0 / bpm).round() as u64;
+    micros.clamp(1,0xFFFFFF) as u32
+}
+
 impl MetaEvent {
 
+    /// Decode the data of this event as text in `encoding`, e.g.
+    /// the text of a lyric or marker event.  Anything that can't be
+    /// decoded is replaced.
+    #[cfg(feature = "std")]
+    pub fn text(&self, encoding: EncodingRef) -> String {
+        decode_text(&self.data, encoding)
+    }
+
+    // the data of this event as latin1 text, for Display
+    #[cfg(feature = "std")]
+    fn latin1_text(&self) -> String {
+        self.text(ISO_8859_1)
+    }
+
+    // latin1 maps each byte to the char with the same value, so this
+    // doesn't need the encoding crate
+    #[cfg(not(feature = "std"))]
+    fn latin1_text(&self) -> String {
+        self.data.iter().map(|&b| b as char).collect()
+    }
+
+    /// The command byte this event is written with.  This is
+    /// `command` for known commands, and the `raw_command` field for
+    /// `MetaCommand::Unknown` events, e.g. ones that were read from a
+    /// file or created with `MetaEvent::raw`
+    pub fn raw_command(&self) -> u8 {
+        match self.command {
+            MetaCommand::Unknown => self.raw_command,
+            command => command as u8,
+        }
+    }
+
     /// Turn `bytes` bytes of the data of this event into a u64
     pub fn data_as_u64(&self, bytes: usize) -> u64 {
         let mut res = 0;
@@ -150,24 +266,127 @@ impl MetaEvent {
         res
     }
 
+    /// Return the data byte at `index` of this event as a signed
+    /// value, or None if the event doesn't have that many data bytes
+    pub fn data_as_i8(&self, index: usize) -> Option<i8> {
+        self.data.get(index).map(|&byte| byte as i8)
+    }
+
+    /// Return the tempo of a TempoSetting event in microseconds per
+    /// quarter note.  Returns None if this isn't a TempoSetting event,
+    /// or if its data is the wrong length.
+    pub fn tempo_micros_per_quarter(&self) -> Option<u32> {
+        if self.command == MetaCommand::TempoSetting && self.data.len() == 3 {
+            Some(self.data_as_u64(3) as u32)
+        } else {
+            None
+        }
+    }
+
+    /// Return the tempo of a TempoSetting event in beats (quarter
+    /// notes) per minute, or None if this isn't a valid TempoSetting
+    /// event
+    pub fn tempo_bpm(&self) -> Option<f64> {
+        self.tempo_micros_per_quarter().map(|micros| 60_000_000.0 / micros as f64)
+    }
+
+    /// Return the contents of a TimeSignature event as (numerator,
+    /// denominator exponent, midi clocks per metronome tick, 32nd notes
+    /// per quarter note).  Returns None if this isn't a TimeSignature
+    /// event, or if its data is the wrong length.
+    pub fn time_signature_data(&self) -> Option<(u8,u8,u8,u8)> {
+        if self.command == MetaCommand::TimeSignature && self.data.len() == 4 {
+            Some((self.data[0],self.data[1],self.data[2],self.data[3]))
+        } else {
+            None
+        }
+    }
+
+    /// Return the denominator of a TimeSignature event (i.e.
+    /// 2^exponent), or None if this isn't a valid TimeSignature event
+    pub fn denominator_value(&self) -> Option<u32> {
+        self.time_signature_data().and_then(|(_,exp,_,_)| 2u32.checked_pow(exp as u32))
+    }
+
+    /// Return the contents of a KeySignature event as the number of
+    /// sharps (positive) or flats (negative) and the mode.  Returns
+    /// None if this isn't a KeySignature event, or if its data is
+    /// invalid.
+    pub fn key_signature_data(&self) -> Option<(i8,Mode)> {
+        if self.command != MetaCommand::KeySignature || self.data.len() != 2 {
+            return None;
+        }
+        let mode = match self.data[1] {
+            0 => Mode::Major,
+            1 => Mode::Minor,
+            _ => return None,
+        };
+        Some((self.data[0] as i8, mode))
+    }
+
+    /// Return the name of the key of a KeySignature event, like "Eb
+    /// major" or "A minor", or None if this isn't a valid
+    /// KeySignature event
+    pub fn key_signature_name(&self) -> Option<&'static str> {
+        self.key_signature_data().and_then(|(sharps_flats,mode)| key_name(sharps_flats,mode))
+    }
+
+    /// Return the time of an SMPTEOffset event.  The frame rate is
+    /// taken from the top bits of the hours byte.  Returns None if
+    /// this isn't an SMPTEOffset event, or if its data is the wrong
+    /// length.
+    pub fn smpte_offset_data(&self) -> Option<SmpteOffset> {
+        if self.command != MetaCommand::SMPTEOffset || self.data.len() != 5 {
+            return None;
+        }
+        Some(SmpteOffset {
+            fps: match (self.data[0] >> 5) & 0x03 {
+                0 => 24,
+                1 => 25,
+                2 => 29,
+                _ => 30,
+            },
+            hours: self.data[0] & 0x1F,
+            minutes: self.data[1],
+            seconds: self.data[2],
+            frames: self.data[3],
+            fractional: self.data[4],
+        })
+    }
+
     /// Extract the next meta event from a reader
+    #[cfg(feature = "std")]
     pub fn next_event(reader: &mut dyn Read) -> Result<MetaEvent, MetaError> {
-        let command =
-            match MetaCommand::from_u8(read_byte(reader)?) {
-                Some(c) => {c},
-                None => MetaCommand::Unknown,
-            };
+        let raw_command = read_byte(reader)?;
         let len = match SMFReader::read_vtime(reader) {
             Ok(t) => { t }
             Err(_) => { return Err(MetaError::OtherErr("Couldn't read time for meta command")); }
         };
         let mut data = Vec::new();
         read_amount(reader,&mut data,len as usize)?;
-        Ok(MetaEvent{
-            command: command,
-            length: len,
-            data: data
-        })
+        Ok(MetaEvent::raw(raw_command,data))
+    }
+
+    /// Parse the meta event at the start of `bytes`, which holds the
+    /// command byte, length and data of the event, as they follow the
+    /// 0xFF in an SMF.  Returns the event and the number of bytes it
+    /// took up.  This doesn't need `std`, so can be used from
+    /// `no_std` code.
+    pub fn from_slice(bytes: &[u8]) -> Result<(MetaEvent,usize), MetaError> {
+        let raw_command = match bytes.first() {
+            Some(&command) => command,
+            None => { return Err(MetaError::OtherErr("Incomplete meta event")); }
+        };
+        let (len,len_bytes) = match decode_vlq_slice(&bytes[1..]) {
+            Some(len) => len,
+            None => { return Err(MetaError::OtherErr("Couldn't read time for meta command")); }
+        };
+        let start = 1 + len_bytes;
+        if ((bytes.len() - start) as u64) < len {
+            return Err(MetaError::OtherErr("Incomplete meta event"));
+        }
+        let end = start + len as usize;
+        Ok((MetaEvent::raw(raw_command,bytes[start..end].to_vec()),end))
     }
 
 
@@ -179,6 +398,19 @@ impl MetaEvent {
         res
     }
 
+    // a text event of type `command` with `text` encoded in
+    // `encoding`.  Characters that can't be encoded become '?'.
+    #[cfg(feature = "std")]
+    fn encoded_text(command: MetaCommand, text: &str, encoding: EncodingRef) -> MetaEvent {
+        let data = encoding.encode(text, EncoderTrap::Replace).unwrap_or_default();
+        MetaEvent {
+            command,
+            raw_command: command as u8,
+            length: data.len() as u64,
+            data,
+        }
+    }
+
     fn u24_to_vec(val: u32) -> Vec<u8> {
         assert!(val <= 2u32.pow(24));
         let mut res = Vec::with_capacity(3);
@@ -190,10 +422,23 @@ impl MetaEvent {
 
     // event constructors below
 
+    /// Create a meta event with any command byte.  `command` is set
+    /// to `MetaCommand::Unknown` if the byte isn't a known command,
+    /// but the original byte is kept and used when writing the event
+    pub fn raw(command: u8, data: Vec<u8>) -> MetaEvent {
+        MetaEvent {
+            command: MetaCommand::from_u8(command).unwrap_or(MetaCommand::Unknown),
+            raw_command: command,
+            length: data.len() as u64,
+            data,
+        }
+    }
+
     /// Create a sequence number meta event
     pub fn sequence_number(sequence_number: u16) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::SequenceNumber,
+            raw_command: MetaCommand::SequenceNumber as u8,
             length: 0x02,
             data: MetaEvent::u16_to_vec(sequence_number),
         }
@@ -203,70 +448,121 @@ impl MetaEvent {
     pub fn text_event(text: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::TextEvent,
+            raw_command: MetaCommand::TextEvent as u8,
             length: text.len() as u64,
             data: text.into_bytes(),
         }
     }
 
+    /// Create a text meta event with the text encoded in `encoding`
+    /// instead of UTF-8, e.g. `encoding::all::ISO_8859_1`
+    #[cfg(feature = "std")]
+    pub fn text_event_encoded(text: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::TextEvent,text,encoding)
+    }
+
     /// Create a copyright notice meta event
     pub fn copyright_notice(copyright: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::CopyrightNotice,
+            raw_command: MetaCommand::CopyrightNotice as u8,
             length: copyright.len() as u64,
             data: copyright.into_bytes(),
         }
     }
 
+    /// Like `copyright_notice`, but with the text encoded in `encoding`
+    #[cfg(feature = "std")]
+    pub fn copyright_notice_encoded(copyright: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::CopyrightNotice,copyright,encoding)
+    }
+
     /// Create a name meta event
     pub fn sequence_or_track_name(name: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::SequenceOrTrackName,
+            raw_command: MetaCommand::SequenceOrTrackName as u8,
             length: name.len() as u64,
             data: name.into_bytes(),
         }
     }
 
+    /// Like `sequence_or_track_name`, but with the text encoded in `encoding`
+    #[cfg(feature = "std")]
+    pub fn sequence_or_track_name_encoded(name: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::SequenceOrTrackName,name,encoding)
+    }
+
     /// Create an instrument name meta event
     pub fn instrument_name(name: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::InstrumentName,
+            raw_command: MetaCommand::InstrumentName as u8,
             length: name.len() as u64,
             data: name.into_bytes(),
         }
     }
 
+    /// Like `instrument_name`, but with the text encoded in `encoding`
+    #[cfg(feature = "std")]
+    pub fn instrument_name_encoded(name: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::InstrumentName,name,encoding)
+    }
+
     /// Create a lyric text meta event
     pub fn lyric_text(text: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::LyricText,
+            raw_command: MetaCommand::LyricText as u8,
             length: text.len() as u64,
             data: text.into_bytes(),
         }
     }
 
+    /// Like `lyric_text`, but with the text encoded in `encoding`
+    #[cfg(feature = "std")]
+    pub fn lyric_text_encoded(text: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::LyricText,text,encoding)
+    }
+
 
     /// Create a marker text meta event
     pub fn marker_text(text: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::MarkerText,
+            raw_command: MetaCommand::MarkerText as u8,
             length: text.len() as u64,
             data: text.into_bytes(),
         }
     }
 
+    /// Like `marker_text`, but with the text encoded in `encoding`
+    #[cfg(feature = "std")]
+    pub fn marker_text_encoded(text: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::MarkerText,text,encoding)
+    }
+
     /// Create a cue point meta event
     pub fn cue_point(text: String) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::CuePoint,
+            raw_command: MetaCommand::CuePoint as u8,
             length: text.len() as u64,
             data: text.into_bytes(),
         }
     }
 
+    /// Like `cue_point`, but with the text encoded in `encoding`
+    #[cfg(feature = "std")]
+    pub fn cue_point_encoded(text: &str, encoding: EncodingRef) -> MetaEvent {
+        MetaEvent::encoded_text(MetaCommand::CuePoint,text,encoding)
+    }
+
     /// Create a midi channel prefix assignment meta event
     pub fn midichannel_prefix_assignment(channel: u8) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::MIDIChannelPrefixAssignment,
+            raw_command: MetaCommand::MIDIChannelPrefixAssignment as u8,
             length: 1,
             data: vec![channel],
         }
@@ -276,6 +572,7 @@ impl MetaEvent {
     pub fn midiport_prefix_assignment(port: u8) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::MIDIPortPrefixAssignment,
+            raw_command: MetaCommand::MIDIPortPrefixAssignment as u8,
             length: 1,
             data: vec![port],
         }
@@ -285,6 +582,7 @@ impl MetaEvent {
     pub fn end_of_track() -> MetaEvent {
         MetaEvent {
             command: MetaCommand::EndOfTrack,
+            raw_command: MetaCommand::EndOfTrack as u8,
             length: 0,
             data: vec![],
         }
@@ -296,15 +594,26 @@ impl MetaEvent {
     pub fn tempo_setting(tempo: u32) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::TempoSetting,
+            raw_command: MetaCommand::TempoSetting as u8,
             length: 3,
             data: MetaEvent::u24_to_vec(tempo),
         }
     }
 
+    /// Create an event to set track tempo to `bpm` beats (quarter
+    /// notes) per minute.  The tempo is rounded to the nearest
+    /// microsecond per quarter note and clamped to what fits in the
+    /// event's 24-bit value.
+    #[cfg(feature = "std")]
+    pub fn tempo_from_bpm(bpm: f64) -> MetaEvent {
+        MetaEvent::tempo_setting(bpm_to_micros(bpm))
+    }
+
     /// Create an smpte offset meta event
     pub fn smpte_offset(hours: u8, minutes: u8, seconds: u8, frames: u8, fractional: u8) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::SMPTEOffset,
+            raw_command: MetaCommand::SMPTEOffset as u8,
             length: 5,
             data: vec![hours,minutes,seconds,frames,fractional],
         }
@@ -325,6 +634,7 @@ impl MetaEvent {
     pub fn time_signature(numerator: u8, denominator: u8, clocks_per_tick: u8, num_32nd_notes_per_24_clocks: u8) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::TimeSignature,
+            raw_command: MetaCommand::TimeSignature as u8,
             length: 4,
             data: vec![numerator,denominator,clocks_per_tick,num_32nd_notes_per_24_clocks],
         }
@@ -338,6 +648,7 @@ impl MetaEvent {
     pub fn key_signature(sharps_flats: u8, major_minor: u8) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::KeySignature,
+            raw_command: MetaCommand::KeySignature as u8,
             length: 2,
             data: vec![sharps_flats, major_minor],
         }
@@ -349,9 +660,193 @@ impl MetaEvent {
     pub fn sequencer_specific_event(data: Vec<u8>) -> MetaEvent {
         MetaEvent {
             command: MetaCommand::SequencerSpecificEvent,
+            raw_command: MetaCommand::SequencerSpecificEvent as u8,
             length: data.len() as u64,
             data: data,
         }
     }
 
 }
+
+#[test]
+fn time_signature_data() {
+    let six_eight = MetaEvent::time_signature(6,3,36,8);
+    assert_eq!(six_eight.time_signature_data(),Some((6,3,36,8)));
+    assert_eq!(six_eight.denominator_value(),Some(8));
+
+    let tempo = MetaEvent::tempo_setting(500000);
+    assert_eq!(tempo.time_signature_data(),None);
+    assert_eq!(tempo.denominator_value(),None);
+
+    let mut short = MetaEvent::time_signature(6,3,36,8);
+    short.data.pop();
+    assert_eq!(short.time_signature_data(),None);
+}
+
+#[test]
+fn key_signature_data() {
+    let c_major = MetaEvent::key_signature(0,0);
+    assert_eq!(c_major.key_signature_data(),Some((0,Mode::Major)));
+    assert_eq!(c_major.key_signature_name(),Some("C major"));
+
+    let c_minor = MetaEvent::key_signature(-3i8 as u8,1);
+    assert_eq!(c_minor.key_signature_data(),Some((-3,Mode::Minor)));
+    assert_eq!(c_minor.key_signature_name(),Some("C minor"));
+
+    assert_eq!(MetaEvent::key_signature(0,2).key_signature_data(),None);
+    assert_eq!(MetaEvent::key_signature(8,0).key_signature_name(),None);
+}
+
+#[test]
+fn smpte_offset_data() {
+    let offset = MetaEvent::smpte_offset(1,2,3,4,5);
+    assert_eq!(offset.smpte_offset_data(),Some(SmpteOffset {
+        fps: 24,
+        hours: 1,
+        minutes: 2,
+        seconds: 3,
+        frames: 4,
+        fractional: 5,
+    }));
+    assert_eq!(format!("{}",offset),"Meta Event: SMPTEOffset: 01:02:03:04.05 (24 fps)");
+
+    // 25 fps is encoded in the hours byte
+    let offset = MetaEvent::smpte_offset(0x20 | 10,0,0,0,0);
+    let data = offset.smpte_offset_data().unwrap();
+    assert_eq!((data.fps,data.hours),(25,10));
+    assert_eq!(MetaEvent::end_of_track().smpte_offset_data(),None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn raw_meta_event() {
+    use ::{Event,SMF,SMFWriter,TrackEvent,Track};
+
+    let event = MetaEvent::raw(0x60,vec![1,2,3]);
+    assert_eq!(event.command,MetaCommand::Unknown);
+    assert_eq!(MetaEvent::raw(0x51,vec![7,0xA1,0x20]).command,MetaCommand::TempoSetting);
+
+    // the command byte follows changes to `command`
+    let mut changed = MetaEvent::text_event("a".to_string());
+    changed.command = MetaCommand::MarkerText;
+    assert_eq!(changed.raw_command(),0x06);
+    assert_eq!(changed,MetaEvent::marker_text("a".to_string()));
+
+    let mut writer = SMFWriter::new_with_division(96);
+    writer.add_smf_track(&Track {
+        copyright: None,
+        name: None,
+        instrument: None,
+        events: vec![TrackEvent { vtime: 0, event: Event::Meta(event) }],
+    }).unwrap();
+    let mut bytes = Vec::new();
+    writer.write_all(&mut bytes).unwrap();
+    assert_eq!(&bytes[22..28],&[0x00,0xFF,0x60,0x03,1,2]);
+
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    match smf.tracks[0].events[0].event {
+        Event::Meta(ref m) => {
+            assert_eq!(m.command,MetaCommand::Unknown);
+            assert_eq!(m.raw_command(),0x60);
+            assert_eq!(m.data,vec![1,2,3]);
+        }
+        _ => panic!("expected a meta event"),
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn unknown_meta_round_trip() {
+    use ::{Event,SMF,SMFWriter};
+
+    // one track holding meta 0x09 (device name, not in MetaCommand)
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,11,
+                 0x00,0xFF,0x09,0x03,0x61,0x62,0x63,
+                 0x00,0xFF,0x2F,0x00];
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    match smf.tracks[0].events[0].event {
+        Event::Meta(ref m) => {
+            assert_eq!(m.command,MetaCommand::Unknown);
+            assert_eq!(m.raw_command(),0x09);
+            assert_eq!(format!("{}",m),"Meta Event: Unknown (0x09), length: 3");
+        }
+        _ => panic!("expected a meta event"),
+    }
+
+    let mut written = Vec::new();
+    SMFWriter::from_smf(smf).write_all(&mut written).unwrap();
+    assert_eq!(&written[..],&bytes[..]);
+}
+
+#[test]
+fn signed_data() {
+    let f_minor = MetaEvent::key_signature(-4i8 as u8,1);
+    assert_eq!(f_minor.data_as_i8(0),Some(-4));
+    assert_eq!(f_minor.data_as_i8(1),Some(1));
+    assert_eq!(f_minor.data_as_i8(2),None);
+    assert_eq!(f_minor.key_signature_data(),Some((-4,Mode::Minor)));
+    assert_eq!(f_minor.key_signature_name(),Some("F minor"));
+    assert_eq!(MetaEvent::key_signature(-7i8 as u8,0).data_as_i8(0),Some(-7));
+
+    assert_eq!(MetaEvent::tempo_setting(500000).tempo_micros_per_quarter(),Some(500000));
+    assert_eq!(MetaEvent::tempo_setting(0xFFFFFF).tempo_micros_per_quarter(),Some(0xFFFFFF));
+    assert_eq!(MetaEvent::key_signature(0,0).tempo_micros_per_quarter(),None);
+    assert_eq!(MetaEvent::raw(0x51,vec![7,0xA1]).tempo_micros_per_quarter(),None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn tempo_bpm() {
+    let tempo = MetaEvent::tempo_from_bpm(120.0);
+    assert_eq!(tempo.tempo_micros_per_quarter(),Some(500000));
+    assert_eq!(tempo.tempo_bpm(),Some(120.0));
+    assert_eq!(MetaEvent::tempo_setting(500000).tempo_bpm(),Some(120.0));
+
+    let bpm = MetaEvent::tempo_from_bpm(93.5).tempo_bpm().unwrap();
+    assert!((bpm - 93.5).abs() < 0.001);
+
+    // out of range tempos are clamped
+    assert_eq!(MetaEvent::tempo_from_bpm(1.0).tempo_micros_per_quarter(),Some(0xFFFFFF));
+    assert_eq!(MetaEvent::tempo_from_bpm(0.0).tempo_micros_per_quarter(),Some(0xFFFFFF));
+    assert_eq!(MetaEvent::tempo_from_bpm(1e9).tempo_micros_per_quarter(),Some(1));
+    assert_eq!(MetaEvent::end_of_track().tempo_bpm(),None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn encoded_text() {
+    use encoding::all::{ISO_8859_1,WINDOWS_31J};
+
+    let event = MetaEvent::text_event_encoded("Café",ISO_8859_1);
+    assert_eq!(event.command,MetaCommand::TextEvent);
+    assert_eq!(event.data,vec![0x43,0x61,0x66,0xE9]);
+    assert_eq!(event.length,4);
+    assert_eq!(MetaEvent::text_event("Café".to_string()).data,"Café".as_bytes());
+    assert_eq!(format!("{}",event),"Meta Event: Text Event. Len: 4 Text: Café");
+
+    let name = MetaEvent::sequence_or_track_name_encoded("日本",WINDOWS_31J);
+    assert_eq!(name.command,MetaCommand::SequenceOrTrackName);
+    assert_eq!(name.data,vec![0x93,0xFA,0x96,0x7B]);
+
+    // characters latin1 can't hold are replaced
+    assert_eq!(MetaEvent::lyric_text_encoded("a日",ISO_8859_1).data,b"a?");
+}
+
+#[test]
+fn from_slice() {
+    // a tempo setting followed by the start of an end of track
+    let bytes = [0x51,0x03,0x07,0xA1,0x20,0x2F];
+    let (event,len) = MetaEvent::from_slice(&bytes).unwrap();
+    assert_eq!(len,5);
+    assert_eq!(event,MetaEvent::tempo_setting(500000));
+
+    let (event,len) = MetaEvent::from_slice(&[0x09,0x01,0x61]).unwrap();
+    assert_eq!(len,3);
+    assert_eq!(event.command,MetaCommand::Unknown);
+    assert_eq!(event.raw_command(),0x09);
+
+    assert!(MetaEvent::from_slice(&bytes[..4]).is_err());
+    assert!(MetaEvent::from_slice(&bytes[5..]).is_err());
+    assert!(MetaEvent::from_slice(&[]).is_err());
+}
@@ -1,17 +1,31 @@
+#[cfg(feature = "std")]
 use std::error;
 use std::fmt;
-use std::convert::From;
+#[cfg(feature = "std")]
 use std::io::{Error,Read};
+// there are no io errors without std, so `MidiError::Error` can't occur
+#[cfg(not(feature = "std"))]
+use std::convert::Infallible as Error;
+#[cfg(not(feature = "std"))]
+use alloc::{string::{String,ToString}, vec::Vec};
 
 use num_traits::FromPrimitive;
 
+use util::{combine_7bit, controller_name, controller_number, split_14bit};
+#[cfg(feature = "std")]
 use util::read_byte;
 
 /// An error that can occur trying to parse a midi message
 #[derive(Debug)]
 pub enum MidiError {
     InvalidStatus(u8),
+    /// A channel over 15 was given
+    InvalidChannel(u8),
+    /// A data byte over 127 was given
+    InvalidDataByte(u8),
     OtherErr(&'static str),
+    /// An error reading the message.  Without the `std` feature this
+    /// holds `core::convert::Infallible`, so never happens.
     Error(Error),
 }
 
@@ -21,10 +35,13 @@ impl From<Error> for MidiError {
     }
 }
 
+#[cfg(feature = "std")]
 impl error::Error for MidiError {
     fn description(&self) -> &str {
         match *self {
             MidiError::InvalidStatus(_) => "Midi data has invalid status byte",
+            MidiError::InvalidChannel(_) => "Midi channel out of range",
+            MidiError::InvalidDataByte(_) => "Midi data byte out of range",
             MidiError::OtherErr(_) => "A general midi error has occured",
             MidiError::Error(ref e) => e.description(),
         }
@@ -42,6 +59,8 @@ impl fmt::Display for MidiError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             MidiError::InvalidStatus(ref s) => write!(f,"Invalid Midi status: {}",s),
+            MidiError::InvalidChannel(ref c) => write!(f,"Invalid Midi channel: {}",c),
+            MidiError::InvalidDataByte(ref b) => write!(f,"Invalid Midi data byte: {}",b),
             MidiError::OtherErr(ref s) => write!(f,"Midi Error: {}",s),
             MidiError::Error(ref e) => write!(f,"{}",e),
         }
@@ -50,6 +69,7 @@ impl fmt::Display for MidiError {
 
 /// The status field of a midi message indicates what midi command it
 /// represents and what channel it is on
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 #[derive(Debug, PartialEq, Clone, Copy, FromPrimitive)]
 pub enum Status {
     // voice
@@ -74,12 +94,73 @@ pub enum Status {
     Stop = 0xFC,
     ActiveSensing = 0xFE, // FD also res/unused
     SystemReset = 0xFF,
+
+    /// One of the reserved system statuses (0xF4, 0xF5, 0xF9 or
+    /// 0xFD), or a byte that isn't a status at all
+    Undefined = 0xF4,
+}
+
+impl Status {
+    /// Get the status of a status byte.  The channel bits of channel
+    /// messages are ignored, and bytes that aren't a known status
+    /// give `Status::Undefined`.
+    pub fn from_byte(byte: u8) -> Status {
+        let stat = if byte >= 0xF0 { byte } else { byte & STATUS_MASK };
+        Status::from_u8(stat).unwrap_or(Status::Undefined)
+    }
+
+    /// Get the category of this status.  Control changes are always
+    /// `ChannelVoice` here, since telling channel mode messages apart
+    /// needs the controller number, see `MidiMessage::category`.
+    pub fn category(&self) -> StatusCategory {
+        match *self {
+            Status::NoteOff |
+            Status::NoteOn |
+            Status::PolyphonicAftertouch |
+            Status::ControlChange |
+            Status::ProgramChange |
+            Status::ChannelAftertouch |
+            Status::PitchBend => StatusCategory::ChannelVoice,
+            Status::SysExStart |
+            Status::SysExEnd => StatusCategory::SystemExclusive,
+            Status::MIDITimeCodeQtrFrame |
+            Status::SongPositionPointer |
+            Status::SongSelect |
+            Status::TuneRequest |
+            Status::Undefined => StatusCategory::SystemCommon,
+            Status::TimingClock |
+            Status::Start |
+            Status::Continue |
+            Status::Stop |
+            Status::ActiveSensing |
+            Status::SystemReset => StatusCategory::SystemRealTime,
+        }
+    }
+}
+
+/// The kinds of midi message, as given by `Status::category` and
+/// `MidiMessage::category`.  Only channel messages carry a channel.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum StatusCategory {
+    /// Notes, controllers, program changes, aftertouch and pitch bend
+    ChannelVoice,
+    /// Control changes using controllers 120-127
+    ChannelMode,
+    SystemCommon,
+    SystemRealTime,
+    SystemExclusive,
 }
 
 /// Midi message building and parsing.  See
 /// http://www.midi.org/techspecs/midimessages.php for a description
 /// of the various Midi messages that exist.
-#[derive(Debug, Default)]
+///
+/// Messages are ordered by their status byte, and then by their data
+/// bytes in order.  This is the same order `AbsoluteEvent` uses for
+/// midi events at the same time.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct MidiMessage {
     pub data: Vec<u8>,
 }
@@ -95,16 +176,82 @@ impl Clone for MidiMessage {
 pub const STATUS_MASK: u8 = 0xF0;
 pub const CHANNEL_MASK: u8 = 0x0F;
 
-// Or in the channel bits to a status
+// Or in the channel bits to a status.  Only the low four bits of
+// `channel` are used, so an out of range channel can't change the
+// status.
 #[inline(always)]
 pub fn make_status(status: Status, channel: u8) -> u8 {
-    status as u8 | channel
+    status as u8 | (channel & CHANNEL_MASK)
+}
+
+// Check that `channel` and `data` are in range for a channel message
+fn check_channel_data(channel: u8, data: &[u8]) -> Result<(),MidiError> {
+    if channel > CHANNEL_MASK {
+        return Err(MidiError::InvalidChannel(channel));
+    }
+    match data.iter().find(|&&byte| byte > 0x7F) {
+        Some(&byte) => Err(MidiError::InvalidDataByte(byte)),
+        None => Ok(()),
+    }
 }
 
 impl MidiMessage {
     /// Return the status (type) of this message
     pub fn status(&self) -> Status {
-        Status::from_u8(self.data[0] & STATUS_MASK).unwrap()
+        Status::from_byte(self.data[0])
+    }
+
+    /// Return the full status byte of this message, including the
+    /// channel bits of channel messages.  This is what running status
+    /// compares, unlike `status` which ignores the channel.
+    pub fn raw_status(&self) -> u8 {
+        self.data[0]
+    }
+
+    /// Return the status of this message, treating a NoteOn with a
+    /// velocity of 0 as a NoteOff, as is conventional
+    pub fn effective_status(&self) -> Status {
+        match self.status() {
+            Status::NoteOn if self.velocity() == Some(0) => Status::NoteOff,
+            status => status,
+        }
+    }
+
+    /// Return true if this message is a NoteOff, or a NoteOn with a
+    /// velocity of 0
+    pub fn is_note_off_like(&self) -> bool {
+        self.effective_status() == Status::NoteOff
+    }
+
+    /// Return true if this is a system real-time message (status
+    /// 0xF8 to 0xFF), which never has any data bytes and doesn't
+    /// affect running status
+    pub fn is_realtime(&self) -> bool {
+        self.data[0] >= 0xF8
+    }
+
+    /// Return the category of this message.  This is the category of
+    /// its status, except that control changes using controllers
+    /// 120-127 are `ChannelMode`.
+    pub fn category(&self) -> StatusCategory {
+        match self.controller() {
+            Some(cc) if cc >= 120 => StatusCategory::ChannelMode,
+            _ => self.status().category(),
+        }
+    }
+
+    /// Return a description of this message.  This is the same as
+    /// the `Display` output, except that control changes show the
+    /// name of the controller, e.g.
+    /// "Control Change: Modulation (CC1) = 64 channel: 0"
+    pub fn describe(&self) -> String {
+        match (self.status(),self.controller(),self.controller_value()) {
+            (Status::ControlChange,Some(cc),Some(value)) => {
+                format!("Control Change: {} (CC{}) = {} channel: {}",
+                        controller_name(cc).unwrap_or("Undefined"), cc, value, self.data[0] & CHANNEL_MASK)
+            }
+            _ => self.to_string(),
+        }
     }
 
     /// Return the channel this message is on (TODO: return 0 for messages with no channel)
@@ -128,7 +275,123 @@ impl MidiMessage {
             Status::Continue |
             Status::Stop |
             Status::ActiveSensing |
-            Status::SystemReset => None
+            Status::SystemReset |
+            Status::Undefined => None
+        }
+    }
+
+    /// Return true if this message is the same as `other` apart from
+    /// the channel it's on.  Messages without a channel have to match
+    /// exactly.
+    pub fn eq_ignore_channel(&self, other: &MidiMessage) -> bool {
+        match (self.channel(),other.channel()) {
+            (Some(_),Some(_)) => {
+                self.data[0] & STATUS_MASK == other.data[0] & STATUS_MASK &&
+                    self.data[1..] == other.data[1..]
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Return true if this message is a system exclusive message.
+    /// The data of such a message is the whole packet, starting with
+    /// the SysExStart status
+    pub fn is_sysex(&self) -> bool {
+        !self.data.is_empty() && self.data[0] == Status::SysExStart as u8
+    }
+
+    /// Return true if this message is a SysEx escape packet, as found
+    /// in an SMF.  These start with SysExEnd and either continue a
+    /// SysEx that was split into several packets or hold arbitrary
+    /// bytes that should be sent as is
+    pub fn is_sysex_escape(&self) -> bool {
+        !self.data.is_empty() && self.data[0] == Status::SysExEnd as u8
+    }
+
+    /// Return the note number of a NoteOn, NoteOff or
+    /// PolyphonicAftertouch message, or None for other messages
+    pub fn note(&self) -> Option<u8> {
+        match self.status() {
+            Status::NoteOff |
+            Status::NoteOn |
+            Status::PolyphonicAftertouch => self.data.get(1).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Return the velocity of a NoteOn or NoteOff message, or None
+    /// for other messages
+    pub fn velocity(&self) -> Option<u8> {
+        match self.status() {
+            Status::NoteOff |
+            Status::NoteOn => self.data.get(2).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Return the controller number of a ControlChange message, or
+    /// None for other messages
+    pub fn controller(&self) -> Option<u8> {
+        match self.status() {
+            Status::ControlChange => self.data.get(1).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Return the value of a ControlChange message, or None for
+    /// other messages
+    pub fn controller_value(&self) -> Option<u8> {
+        match self.status() {
+            Status::ControlChange => self.data.get(2).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Return whether a switch controller (64 to 69, like the sustain
+    /// pedal) is on, which is the case for values of 64 and above.
+    /// Returns None for other controllers and other messages.
+    pub fn switch_value(&self) -> Option<bool> {
+        match (self.controller(),self.controller_value()) {
+            (Some(64..=69),Some(value)) => Some(value >= 64),
+            _ => None,
+        }
+    }
+
+    /// Return the program number of a ProgramChange message, or None
+    /// for other messages
+    pub fn program(&self) -> Option<u8> {
+        match self.status() {
+            Status::ProgramChange => self.data.get(1).cloned(),
+            _ => None,
+        }
+    }
+
+    /// Return the fourteen bit value of a PitchBend message, or None
+    /// for other messages.  Center (no pitch change) is 0x2000.
+    pub fn pitch_bend_value(&self) -> Option<u16> {
+        match (self.status(), self.data.get(1), self.data.get(2)) {
+            (Status::PitchBend, Some(&lsb), Some(&msb)) => Some(combine_7bit(lsb,msb)),
+            _ => None,
+        }
+    }
+
+    /// Return the message type (0 to 7) and the four bit value of a
+    /// MIDI Time Code quarter frame message, or None for other
+    /// messages
+    pub fn mtc_quarter_frame_data(&self) -> Option<(u8,u8)> {
+        match (self.status(), self.data.get(1)) {
+            (Status::MIDITimeCodeQtrFrame, Some(&data)) => Some(((data >> 4) & 0x07, data & 0x0F)),
+            _ => None,
+        }
+    }
+
+    /// Return the fourteen bit position in MIDI beats (sixteenth
+    /// notes) of a Song Position Pointer message, or None for other
+    /// messages
+    pub fn song_position_value(&self) -> Option<u16> {
+        match (self.status(), self.data.get(1), self.data.get(2)) {
+            (Status::SongPositionPointer, Some(&lsb), Some(&msb)) => Some(combine_7bit(lsb,msb)),
+            _ => None,
         }
     }
 
@@ -148,54 +411,90 @@ impl MidiMessage {
         }
     }
 
+    /// Get the bytes of this message as they are sent over the wire.
+    /// A SysEx message that doesn't end with SysExEnd (e.g. the first
+    /// packet of a SysEx that was split up in an SMF) has it added.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.data.clone();
+        if self.missing_sysex_end() {
+            bytes.push(Status::SysExEnd as u8);
+        }
+        bytes
+    }
+
+    /// Return the number of bytes in this message, including the
+    /// status byte.  This is the length of `to_bytes`.
+    pub fn byte_len(&self) -> usize {
+        if self.missing_sysex_end() {
+            self.data.len() + 1
+        } else {
+            self.data.len()
+        }
+    }
+
+    // true for a SysEx message that isn't terminated with SysExEnd
+    fn missing_sysex_end(&self) -> bool {
+        self.is_sysex() && self.data.last() != Some(&(Status::SysExEnd as u8))
+    }
+
     // return the number of data bytes for a message with the given status
     // -1 -> variable sized message, call get_variable_size
     // -2 -> sysex, read until SysExEnd
     // -3 -> invalid status
     pub fn data_bytes(status: u8) -> isize {
-        match Status::from_u8(status & STATUS_MASK) {
-            Some(stat) => {
-                match stat {
-                    Status::NoteOff |
-                    Status::NoteOn |
-                    Status::PolyphonicAftertouch |
-                    Status::ControlChange |
-                    Status::PitchBend |
-                    Status::SongPositionPointer => { 2 }
-
-                    Status::SysExStart => { -2 }
-
-                    Status::ProgramChange |
-                    Status::ChannelAftertouch |
-                    Status::MIDITimeCodeQtrFrame |
-                    Status::SongSelect => { 1 }
-
-                    Status::TuneRequest |
-                    Status::SysExEnd |
-                    Status::TimingClock |
-                    Status::Start |
-                    Status::Continue |
-                    Status::Stop |
-                    Status::ActiveSensing |
-                    Status::SystemReset => { 0 }
-                }
-            }
-            None => -3
+        match Status::from_byte(status) {
+            Status::NoteOff |
+            Status::NoteOn |
+            Status::PolyphonicAftertouch |
+            Status::ControlChange |
+            Status::PitchBend |
+            Status::SongPositionPointer => { 2 }
+
+            Status::SysExStart => { -2 }
+
+            Status::ProgramChange |
+            Status::ChannelAftertouch |
+            Status::MIDITimeCodeQtrFrame |
+            Status::SongSelect => { 1 }
+
+            Status::TuneRequest |
+            Status::SysExEnd |
+            Status::TimingClock |
+            Status::Start |
+            Status::Continue |
+            Status::Stop |
+            Status::ActiveSensing |
+            Status::SystemReset => { 0 }
+
+            Status::Undefined => { -3 }
+        }
+    }
+
+    // The length of a message with status `stat`, including the
+    // status byte, or None for a SysEx message, which goes on until a
+    // SysExEnd.  Fails for statuses that can't start a message.
+    fn message_len(stat: u8) -> Result<Option<usize>, MidiError> {
+        match MidiMessage::data_bytes(stat) {
+            n @ 0..=2 => Ok(Some(n as usize + 1)),
+            -1 => Err(MidiError::OtherErr("Don't handle variable sized yet")),
+            -2 => Ok(None),
+            _ => Err(MidiError::InvalidStatus(stat)),
         }
     }
 
     /// Get the next midi message from the reader given that the
     /// status `stat` has just been read
+    #[cfg(feature = "std")]
     pub fn next_message_given_status(stat: u8, reader: &mut dyn Read) -> Result<MidiMessage, MidiError> {
         let mut ret:Vec<u8> = Vec::with_capacity(3);
         ret.push(stat);
-        match MidiMessage::data_bytes(stat) {
-            0 => {}
-            1 => { ret.push(read_byte(reader)?); }
-            2 => { ret.push(read_byte(reader)?);
-                   ret.push(read_byte(reader)?); }
-            -1 => { return Err(MidiError::OtherErr("Don't handle variable sized yet")); }
-            -2 => {
+        match MidiMessage::message_len(stat)? {
+            Some(len) => {
+                for _ in 1..len {
+                    ret.push(read_byte(reader)?);
+                }
+            }
+            None => {
                 // skip SysEx message
                 while {
                     let byte = read_byte(reader)?;
@@ -203,36 +502,66 @@ impl MidiMessage {
                     byte != Status::SysExEnd as u8
                 } {}
             }
-            _ =>  { return Err(MidiError::InvalidStatus(stat)); }
         }
         Ok(MidiMessage{data: ret})
     }
 
     /// Get the next midi message from the reader given that there's a running
     /// status of `stat` and that in place of a status was read `databyte`
+    #[cfg(feature = "std")]
     pub fn next_message_running_status(stat: u8, databyte: u8, reader: &mut dyn Read) -> Result<MidiMessage, MidiError> {
         let mut ret:Vec<u8> = Vec::with_capacity(3);
         ret.push(stat);
         ret.push(databyte);
-        match MidiMessage::data_bytes(stat) {
-            0 => { panic!("Can't have zero length message with running status"); }
-            1 => { } // already read it
-            2 => { ret.push(read_byte(reader)?); } // only need one more byte
-            -1 => { return Err(MidiError::OtherErr("Don't handle variable sized yet")); }
-            -2 => { return Err(MidiError::OtherErr("Running status not permitted with meta and sysex event")); }
-            _ =>  { return Err(MidiError::InvalidStatus(stat)); }
+        match MidiMessage::message_len(stat)? {
+            Some(1) => { return Err(MidiError::OtherErr("Running status not permitted with zero length message")); }
+            Some(len) => {
+                // already read the first data byte
+                for _ in 2..len {
+                    ret.push(read_byte(reader)?);
+                }
+            }
+            None => { return Err(MidiError::OtherErr("Running status not permitted with meta and sysex event")); }
         }
         Ok(MidiMessage{data: ret})
     }
 
     /// Extract next midi message from a reader
+    #[cfg(feature = "std")]
     pub fn next_message(reader: &mut dyn Read) -> Result<MidiMessage,MidiError> {
         let stat = read_byte(reader)?;
         MidiMessage::next_message_given_status(stat,reader)
     }
 
+    /// Parse the midi message at the start of `bytes`, which must
+    /// begin with a status byte.  Returns the message and the number
+    /// of bytes it took up.  This doesn't need `std`, so can be used
+    /// from `no_std` code.
+    pub fn from_slice(bytes: &[u8]) -> Result<(MidiMessage,usize), MidiError> {
+        let stat = match bytes.first() {
+            Some(&stat) if stat & 0x80 != 0 => stat,
+            Some(&stat) => { return Err(MidiError::InvalidStatus(stat)); }
+            None => { return Err(MidiError::OtherErr("Incomplete midi message")); }
+        };
+        let len = match MidiMessage::message_len(stat)? {
+            Some(len) => len,
+            None => {
+                match bytes.iter().position(|&b| b == Status::SysExEnd as u8) {
+                    Some(end) => end + 1,
+                    None => { return Err(MidiError::OtherErr("Incomplete midi message")); }
+                }
+            }
+        };
+        if bytes.len() < len {
+            return Err(MidiError::OtherErr("Incomplete midi message"));
+        }
+        Ok((MidiMessage{data: bytes[..len].to_vec()},len))
+    }
+
 
-    // Functions to build midi messages
+    // Functions to build midi messages.  These take the channel
+    // modulo 16 and don't check the data bytes, see the `_checked`
+    // variants for ones that do.
 
     /// Create a note on message
     pub fn note_on(note: u8, velocity: u8, channel: u8) -> MidiMessage {
@@ -241,6 +570,13 @@ impl MidiMessage {
         }
     }
 
+    /// Create a note on message, failing if `note` or `velocity` is
+    /// over 127 or `channel` is over 15
+    pub fn note_on_checked(note: u8, velocity: u8, channel: u8) -> Result<MidiMessage,MidiError> {
+        check_channel_data(channel,&[note,velocity])?;
+        Ok(MidiMessage::note_on(note,velocity,channel))
+    }
+
     /// Create a note off message
     pub fn note_off(note: u8, velocity: u8, channel: u8) -> MidiMessage {
         MidiMessage {
@@ -248,6 +584,13 @@ impl MidiMessage {
         }
     }
 
+    /// Create a note off message, failing if `note` or `velocity` is
+    /// over 127 or `channel` is over 15
+    pub fn note_off_checked(note: u8, velocity: u8, channel: u8) -> Result<MidiMessage,MidiError> {
+        check_channel_data(channel,&[note,velocity])?;
+        Ok(MidiMessage::note_off(note,velocity,channel))
+    }
+
     /// Create a polyphonic aftertouch message
     /// This message is most often sent by pressing down on the key after it "bottoms out".
     pub fn polyphonic_aftertouch(note: u8, pressure: u8, channel: u8) -> MidiMessage {
@@ -265,6 +608,14 @@ impl MidiMessage {
         }
     }
 
+    /// Create a control change message for a controller given by
+    /// name, e.g. "modulation", "volume", "pan", "expression" or
+    /// "sustain".  See `controller_number` for the names that are
+    /// understood.  Returns None for an unknown name.
+    pub fn cc_by_name(name: &str, value: u8, channel: u8) -> Option<MidiMessage> {
+        controller_number(name).map(|cc| MidiMessage::control_change(cc,value,channel))
+    }
+
     /// Create a program change message
     /// This message sent when the patch number changes. `program` is the new program number.
     pub fn program_change(program: u8, channel: u8) -> MidiMessage {
@@ -294,6 +645,210 @@ impl MidiMessage {
         }
     }
 
+    /// Create the pair of control change messages that select a bank.
+    /// `bank` is a fourteen bit value, the first message is the bank select MSB
+    /// (controller 0) and the second the bank select LSB (controller 32).
+    pub fn bank_select(bank: u16, channel: u8) -> (MidiMessage, MidiMessage) {
+        let (lsb,msb) = split_14bit(bank);
+        (MidiMessage::control_change(0,msb,channel),
+         MidiMessage::control_change(32,lsb,channel))
+    }
+
+    /// Create the messages to select a bank and then a program in it: the bank
+    /// select MSB and LSB followed by a program change.
+    pub fn program_with_bank(bank: u16, program: u8, channel: u8) -> Vec<MidiMessage> {
+        let (msb,lsb) = MidiMessage::bank_select(bank,channel);
+        vec![msb, lsb, MidiMessage::program_change(program,channel)]
+    }
+
+    /// Create the sequence of control change messages that sets registered
+    /// parameter `param` to `value`.  Both are fourteen bit values.
+    ///
+    /// The sequence is the parameter number MSB (controller 101) and LSB
+    /// (controller 100), then the value split into data entry MSB
+    /// (controller 6, the upper seven bits) and data entry LSB (controller 38,
+    /// the lower seven bits), and finally the null RPN (101 and 100 set to 127)
+    /// so later data entry messages don't change the parameter by accident.
+    /// For pitch bend sensitivity (RPN 0) the MSB is semitones and the LSB cents.
+    pub fn rpn(param: u16, value: u16, channel: u8) -> Vec<MidiMessage> {
+        MidiMessage::parameter_sequence(101,100,param,value,channel)
+    }
+
+    /// Create the sequence of control change messages that sets non-registered
+    /// parameter `param` to `value`.  This is the same as `rpn`, except the
+    /// parameter number is sent with controllers 99 (MSB) and 98 (LSB).  The
+    /// sequence still ends with the null RPN.
+    pub fn nrpn(param: u16, value: u16, channel: u8) -> Vec<MidiMessage> {
+        MidiMessage::parameter_sequence(99,98,param,value,channel)
+    }
+
+    fn parameter_sequence(msb_cc: u8, lsb_cc: u8, param: u16, value: u16, channel: u8) -> Vec<MidiMessage> {
+        let (param_lsb,param_msb) = split_14bit(param);
+        let (value_lsb,value_msb) = split_14bit(value);
+        vec![
+            MidiMessage::control_change(msb_cc,param_msb,channel),
+            MidiMessage::control_change(lsb_cc,param_lsb,channel),
+            MidiMessage::control_change(6,value_msb,channel),
+            MidiMessage::control_change(38,value_lsb,channel),
+            MidiMessage::control_change(101,127,channel),
+            MidiMessage::control_change(100,127,channel),
+        ]
+    }
+
+    /// Create a system exclusive message holding `data`, which
+    /// should not include the SysExStart and SysExEnd bytes.  The
+    /// message is framed with both, so its bytes always end with the
+    /// terminating 0xF7.
+    pub fn sysex(data: &[u8]) -> MidiMessage {
+        let mut bytes = Vec::with_capacity(data.len() + 2);
+        bytes.push(Status::SysExStart as u8);
+        bytes.extend_from_slice(data);
+        bytes.push(Status::SysExEnd as u8);
+        MidiMessage {
+            data: bytes,
+        }
+    }
+
+    /// Create an all sound off message (controller 120)
+    /// Mutes all sounding notes immediately, including their release.
+    pub fn all_sound_off(channel: u8) -> MidiMessage {
+        MidiMessage::control_change(120,0,channel)
+    }
+
+    /// Create a sustain (damper) pedal message (controller 64)
+    pub fn sustain(on: bool, channel: u8) -> MidiMessage {
+        MidiMessage::control_change(64,if on { 127 } else { 0 },channel)
+    }
+
+    /// Create a reset all controllers message (controller 121)
+    pub fn reset_all_controllers(channel: u8) -> MidiMessage {
+        MidiMessage::control_change(121,0,channel)
+    }
+
+    /// Create a local control message (controller 122)
+    /// When `on` is false the instrument's keyboard is disconnected from its sound generator.
+    pub fn local_control(channel: u8, on: bool) -> MidiMessage {
+        MidiMessage::control_change(122,if on { 127 } else { 0 },channel)
+    }
+
+    /// Create an all notes off message (controller 123)
+    pub fn all_notes_off(channel: u8) -> MidiMessage {
+        MidiMessage::control_change(123,0,channel)
+    }
+
+    /// Create a Song Position Pointer message.  `beats` is the
+    /// position in MIDI beats (sixteenth notes) since the start of the
+    /// song, of which only the low fourteen bits are used.
+    pub fn song_position(beats: u16) -> MidiMessage {
+        let (lsb,msb) = split_14bit(beats);
+        MidiMessage {
+            data: vec![Status::SongPositionPointer as u8, lsb, msb],
+        }
+    }
+
+    /// Create a MIDI Time Code quarter frame message.  `message_type`
+    /// (0 to 7) says which piece of the time code this is, from the
+    /// frames low nibble (0) to the hours high nibble and frame rate
+    /// (7), and `value` is that four bit piece.
+    pub fn mtc_quarter_frame(message_type: u8, value: u8) -> MidiMessage {
+        MidiMessage {
+            data: vec![Status::MIDITimeCodeQtrFrame as u8, (message_type & 0x07) << 4 | (value & 0x0F)],
+        }
+    }
+
+}
+
+/// A MidiStreamParser builds midi messages out of a live stream of
+/// bytes, such as those from a serial or USB midi input, which don't
+/// have any SMF framing.  Bytes are fed in one at a time with `push`,
+/// which returns a message whenever one is complete.  Running status
+/// is supported, and real-time messages (0xF8-0xFF) are returned as
+/// soon as they are seen, even in the middle of another message.
+/// Data bytes that don't belong to any message are ignored.
+#[derive(Debug, Default)]
+pub struct MidiStreamParser {
+    // status of the last channel message, or 0 if there's none
+    running_status: u8,
+    // the message being built, starting with its status
+    buffer: Vec<u8>,
+    // number of data bytes the message in `buffer` needs
+    expected: usize,
+    in_sysex: bool,
+}
+
+impl MidiStreamParser {
+    /// Create a new parser with no running status
+    pub fn new() -> MidiStreamParser {
+        MidiStreamParser::default()
+    }
+
+    /// Feed the next byte of the stream to the parser.  Returns the
+    /// message this byte completes, if any.
+    pub fn push(&mut self, byte: u8) -> Option<MidiMessage> {
+        if byte >= 0xF8 {
+            // real-time, doesn't affect any message in progress
+            return match byte {
+                0xF9 | 0xFD => None, // undefined
+                _ => Some(MidiMessage { data: vec![byte] }),
+            };
+        }
+        if byte & 0x80 == 0 {
+            return self.push_data(byte);
+        }
+
+        if self.in_sysex {
+            self.in_sysex = false;
+            if byte == Status::SysExEnd as u8 {
+                self.buffer.push(byte);
+                return Some(MidiMessage { data: self.buffer.split_off(0) });
+            }
+            // any other status ends the SysEx without a proper end, so drop it
+        }
+        self.buffer.clear();
+        if byte < 0xF0 {
+            self.running_status = byte;
+            self.start(byte,MidiMessage::data_bytes(byte) as usize);
+            return None;
+        }
+        // system common messages clear the running status
+        self.running_status = 0;
+        match byte {
+            0xF0 => {
+                self.buffer.push(byte);
+                self.in_sysex = true;
+                None
+            }
+            0xF1 | 0xF3 => { self.start(byte,1); None }
+            0xF2 => { self.start(byte,2); None }
+            0xF6 => Some(MidiMessage { data: vec![byte] }),
+            _ => None, // undefined, or a SysExEnd without a SysEx
+        }
+    }
+
+    fn start(&mut self, status: u8, expected: usize) {
+        self.buffer.push(status);
+        self.expected = expected;
+    }
+
+    fn push_data(&mut self, byte: u8) -> Option<MidiMessage> {
+        if self.in_sysex {
+            self.buffer.push(byte);
+            return None;
+        }
+        if self.buffer.is_empty() {
+            if self.running_status == 0 {
+                return None;
+            }
+            let status = self.running_status;
+            self.start(status,MidiMessage::data_bytes(status) as usize);
+        }
+        self.buffer.push(byte);
+        if self.buffer.len() > self.expected {
+            Some(MidiMessage { data: self.buffer.split_off(0) })
+        } else {
+            None
+        }
+    }
 }
 
 impl fmt::Display for Status {
@@ -319,6 +874,7 @@ impl fmt::Display for Status {
                    Status::Stop => "Stop",
                    Status::ActiveSensing => "Active Sensing",
                    Status::SystemReset => "System Reset",
+                   Status::Undefined => "Undefined",
                })
     }
 }
@@ -339,3 +895,265 @@ impl fmt::Display for MidiMessage {
         }
     }
 }
+
+#[test]
+fn typed_accessors() {
+    let note_on = MidiMessage::note_on(60,100,3);
+    assert_eq!(note_on.note(),Some(60));
+    assert_eq!(note_on.velocity(),Some(100));
+    assert_eq!(note_on.controller(),None);
+
+    let cc = MidiMessage::control_change(7,90,0);
+    assert_eq!(cc.controller(),Some(7));
+    assert_eq!(cc.controller_value(),Some(90));
+    assert_eq!(cc.note(),None);
+
+    assert_eq!(MidiMessage::program_change(40,0).program(),Some(40));
+    assert_eq!(MidiMessage::pitch_bend(0,0x40,0).pitch_bend_value(),Some(0x2000));
+    assert_eq!(MidiMessage::pitch_bend(0x7F,0x7F,0).pitch_bend_value(),Some(0x3FFF));
+    assert_eq!(note_on.pitch_bend_value(),None);
+}
+
+#[test]
+fn switch_value() {
+    assert_eq!(MidiMessage::control_change(64,127,0).switch_value(),Some(true));
+    assert_eq!(MidiMessage::control_change(64,0,0).switch_value(),Some(false));
+    assert_eq!(MidiMessage::control_change(66,64,3).switch_value(),Some(true));
+    assert_eq!(MidiMessage::control_change(69,63,3).switch_value(),Some(false));
+    assert_eq!(MidiMessage::control_change(7,127,0).switch_value(),None);
+    assert_eq!(MidiMessage::note_on(64,127,0).switch_value(),None);
+
+    assert_eq!(MidiMessage::sustain(true,1).data,vec![0xB1,64,127]);
+    assert_eq!(MidiMessage::sustain(false,1).data,vec![0xB1,64,0]);
+    assert_eq!(MidiMessage::sustain(true,1).switch_value(),Some(true));
+}
+
+#[test]
+fn mtc_quarter_frame() {
+    let msg = MidiMessage::mtc_quarter_frame(3,5);
+    assert_eq!(msg.data,vec![0xF1,0x35]);
+    assert_eq!(msg.status(),Status::MIDITimeCodeQtrFrame);
+    assert_eq!(msg.mtc_quarter_frame_data(),Some((3,5)));
+    assert_eq!(MidiMessage::mtc_quarter_frame(7,0x0F).mtc_quarter_frame_data(),Some((7,0x0F)));
+    assert_eq!(MidiMessage::note_on(0x35,100,0).mtc_quarter_frame_data(),None);
+}
+
+#[test]
+fn song_position() {
+    let msg = MidiMessage::song_position(1000);
+    assert_eq!(msg.data,vec![0xF2,0x68,0x07]);
+    assert_eq!(msg.song_position_value(),Some(1000));
+    assert_eq!(MidiMessage::song_position(0x3FFF).song_position_value(),Some(0x3FFF));
+    assert_eq!(MidiMessage::pitch_bend(0x68,0x07,0).song_position_value(),None);
+}
+
+#[test]
+fn ordering() {
+    let mut msgs = vec![
+        MidiMessage::note_on(64,100,0),
+        MidiMessage::control_change(7,100,0),
+        MidiMessage::note_off(60,0,0),
+        MidiMessage::note_on(60,100,1),
+        MidiMessage::note_on(60,100,0),
+        MidiMessage::note_on(60,90,0),
+    ];
+    msgs.sort();
+    let data: Vec<Vec<u8>> = msgs.into_iter().map(|m| m.data).collect();
+    assert_eq!(data,vec![vec![0x80,60,0],
+                         vec![0x90,60,90],
+                         vec![0x90,60,100],
+                         vec![0x90,64,100],
+                         vec![0x91,60,100],
+                         vec![0xB0,7,100]]);
+    assert_eq!(MidiMessage::note_on(60,100,0),MidiMessage::note_on(60,100,0));
+    assert!(MidiMessage::note_on(60,100,0) != MidiMessage::note_on(60,101,0));
+}
+
+#[test]
+fn out_of_range_values() {
+    // the channel can't spill into the status
+    assert_eq!(MidiMessage::note_on(60,100,16).data,vec![0x90,60,100]);
+    assert_eq!(MidiMessage::note_on(60,100,16).channel(),Some(0));
+    assert_eq!(MidiMessage::control_change(7,100,20).data,vec![0xB4,7,100]);
+
+    match MidiMessage::note_on_checked(60,100,16) {
+        Err(MidiError::InvalidChannel(16)) => {}
+        other => panic!("expected an invalid channel, got {:?}",other),
+    }
+    match MidiMessage::note_off_checked(60,128,0) {
+        Err(MidiError::InvalidDataByte(128)) => {}
+        other => panic!("expected an invalid data byte, got {:?}",other),
+    }
+    assert_eq!(MidiMessage::note_on_checked(127,127,15).unwrap().data,vec![0x9F,127,127]);
+    assert_eq!(MidiMessage::note_off_checked(60,0,1).unwrap(),MidiMessage::note_off(60,0,1));
+}
+
+#[test]
+fn channel_mode_messages() {
+    assert_eq!(MidiMessage::all_notes_off(0).data,vec![0xB0,123,0]);
+    assert_eq!(MidiMessage::all_sound_off(2).data,vec![0xB2,120,0]);
+    assert_eq!(MidiMessage::reset_all_controllers(15).data,vec![0xBF,121,0]);
+    assert_eq!(MidiMessage::local_control(1,true).data,vec![0xB1,122,127]);
+    assert_eq!(MidiMessage::local_control(1,false).data,vec![0xB1,122,0]);
+}
+
+#[test]
+fn bank_select() {
+    let (msb,lsb) = MidiMessage::bank_select(0x0081,0);
+    assert_eq!(msb.data,vec![0xB0,0,1]);
+    assert_eq!(lsb.data,vec![0xB0,32,1]);
+
+    let msgs = MidiMessage::program_with_bank(0x3FFF,5,9);
+    assert_eq!(msgs.len(),3);
+    assert_eq!(msgs[0].data,vec![0xB9,0,0x7F]);
+    assert_eq!(msgs[1].data,vec![0xB9,32,0x7F]);
+    assert_eq!(msgs[2].data,vec![0xC9,5]);
+}
+
+#[test]
+fn rpn_sequence() {
+    // pitch bend sensitivity of 2 semitones, 0 cents
+    let msgs: Vec<Vec<u8>> = MidiMessage::rpn(0,2 << 7,0).into_iter().map(|m| m.data).collect();
+    assert_eq!(msgs,vec![vec![0xB0,101,0],
+                         vec![0xB0,100,0],
+                         vec![0xB0,6,2],
+                         vec![0xB0,38,0],
+                         vec![0xB0,101,127],
+                         vec![0xB0,100,127]]);
+
+    let msgs = MidiMessage::nrpn(0x0105,0x3FFF,3);
+    assert_eq!(msgs[0].data,vec![0xB3,99,2]);
+    assert_eq!(msgs[1].data,vec![0xB3,98,5]);
+    assert_eq!(msgs[2].data,vec![0xB3,6,0x7F]);
+    assert_eq!(msgs[3].data,vec![0xB3,38,0x7F]);
+}
+
+#[test]
+fn note_off_like() {
+    let off = MidiMessage::note_on(60,0,0);
+    assert_eq!(off.status(),Status::NoteOn);
+    assert_eq!(off.effective_status(),Status::NoteOff);
+    assert!(off.is_note_off_like());
+    assert!(MidiMessage::note_off(60,64,0).is_note_off_like());
+    assert!(!MidiMessage::note_on(60,1,0).is_note_off_like());
+    assert_eq!(MidiMessage::control_change(7,0,0).effective_status(),Status::ControlChange);
+}
+
+#[test]
+fn describe_controllers() {
+    assert_eq!(MidiMessage::control_change(1,64,0).describe(),"Control Change: Modulation (CC1) = 64 channel: 0");
+    assert_eq!(MidiMessage::control_change(64,127,3).describe(),"Control Change: Sustain Pedal (CC64) = 127 channel: 3");
+    assert_eq!(MidiMessage::control_change(3,0,0).describe(),"Control Change: Undefined (CC3) = 0 channel: 0");
+    let note = MidiMessage::note_on(60,100,0);
+    assert_eq!(note.describe(),note.to_string());
+}
+
+#[test]
+fn message_bytes() {
+    let note_on = MidiMessage::note_on(60,100,2);
+    assert_eq!(note_on.to_bytes(),vec![0x92,60,100]);
+    assert_eq!(note_on.byte_len(),3);
+    assert_eq!(MidiMessage::control_change(7,100,0).byte_len(),3);
+    assert_eq!(MidiMessage::program_change(7,0).byte_len(),2);
+
+    let sysex = MidiMessage::sysex(&[0x7E,0x7F,0x09,0x01]);
+    assert_eq!(sysex.to_bytes(),vec![0xF0,0x7E,0x7F,0x09,0x01,0xF7]);
+    assert!(sysex.is_sysex());
+    assert_eq!(sysex.byte_len(),6);
+
+    // unterminated SysEx messages are terminated on the wire
+    let unterminated = MidiMessage::from_bytes(vec![0xF0,0x7E,0x7F]);
+    assert_eq!(unterminated.to_bytes(),vec![0xF0,0x7E,0x7F,0xF7]);
+    assert_eq!(unterminated.byte_len(),4);
+}
+
+#[test]
+fn stream_parser() {
+    let mut parser = MidiStreamParser::new();
+    let bytes = [
+        0x90, 0xF8, 60, 0xF8, 100,  // note on with timing clocks inside
+        62, 100,                    // running status
+        0xFE,                       // active sensing
+        64,                         // incomplete
+        0xB1, 7, 0xFA, 90,          // control change with a start inside
+        0xF0, 0x7E, 0xF8, 0x09, 0xF7, // sysex with a timing clock inside
+        1, 2,                       // no running status after sysex
+        0xC0, 5, 6,                 // running status program changes
+    ];
+    let messages: Vec<Vec<u8>> = bytes.iter().filter_map(|b| parser.push(*b)).map(|m| m.data).collect();
+    assert_eq!(messages,vec![vec![0xF8],
+                             vec![0xF8],
+                             vec![0x90,60,100],
+                             vec![0x90,62,100],
+                             vec![0xFE],
+                             vec![0xFA],
+                             vec![0xB1,7,90],
+                             vec![0xF8],
+                             vec![0xF0,0x7E,0x09,0xF7],
+                             vec![0xC0,5],
+                             vec![0xC0,6]]);
+
+    // a message split across several pushes
+    let mut parser = MidiStreamParser::new();
+    assert!(parser.push(0xE0).is_none());
+    assert!(parser.push(0x00).is_none());
+    assert_eq!(parser.push(0x40).unwrap().data,vec![0xE0,0x00,0x40]);
+    assert_eq!(parser.push(0xF6).unwrap().data,vec![0xF6]);
+    assert!(parser.push(0x10).is_none());
+}
+
+#[test]
+fn from_slice() {
+    let bytes = [0x91,60,100,0xF0,0x7E,0xF7,0x80];
+    let (msg,len) = MidiMessage::from_slice(&bytes).unwrap();
+    assert_eq!(len,3);
+    assert_eq!(msg,MidiMessage::note_on(60,100,1));
+    let (msg,len) = MidiMessage::from_slice(&bytes[3..]).unwrap();
+    assert_eq!(len,3);
+    assert_eq!(msg.data,[0xF0,0x7E,0xF7]);
+
+    assert!(MidiMessage::from_slice(&bytes[6..]).is_err());
+    assert!(MidiMessage::from_slice(&bytes[1..]).is_err());
+    assert!(MidiMessage::from_slice(&[]).is_err());
+}
+
+#[test]
+fn cc_by_name() {
+    assert_eq!(MidiMessage::cc_by_name("pan",64,2),Some(MidiMessage::control_change(10,64,2)));
+    assert_eq!(MidiMessage::cc_by_name("Expression",100,0).unwrap().controller(),Some(11));
+    assert_eq!(MidiMessage::cc_by_name("modulation",1,0).unwrap().controller(),Some(1));
+    assert!(MidiMessage::cc_by_name("wah",0,0).is_none());
+}
+
+#[test]
+fn categories() {
+    assert_eq!(Status::NoteOn.category(),StatusCategory::ChannelVoice);
+    assert_eq!(Status::TimingClock.category(),StatusCategory::SystemRealTime);
+    assert_eq!(Status::SongSelect.category(),StatusCategory::SystemCommon);
+    assert_eq!(Status::SysExStart.category(),StatusCategory::SystemExclusive);
+
+    assert_eq!(MidiMessage::note_on(60,100,3).category(),StatusCategory::ChannelVoice);
+    assert_eq!(MidiMessage::control_change(7,100,0).category(),StatusCategory::ChannelVoice);
+    assert_eq!(MidiMessage::all_sound_off(0).category(),StatusCategory::ChannelMode);
+    assert_eq!(MidiMessage { data: vec![0xF8] }.category(),StatusCategory::SystemRealTime);
+}
+
+#[test]
+fn eq_ignore_channel() {
+    let ch0 = MidiMessage::note_on(60,100,0);
+    let ch5 = MidiMessage::note_on(60,100,5);
+    assert!(ch0 != ch5);
+    assert!(ch0.eq_ignore_channel(&ch5));
+    assert!(!ch0.eq_ignore_channel(&MidiMessage::note_on(60,101,5)));
+    assert!(!ch0.eq_ignore_channel(&MidiMessage::note_off(60,100,5)));
+    assert!(MidiMessage::song_position(16).eq_ignore_channel(&MidiMessage::song_position(16)));
+    assert!(!MidiMessage::song_position(16).eq_ignore_channel(&MidiMessage::song_position(17)));
+}
+
+#[test]
+fn raw_status() {
+    let msg = MidiMessage::note_on(60,100,5);
+    assert_eq!(msg.raw_status(),0x95);
+    assert_eq!(msg.status(),Status::NoteOn);
+    assert_eq!(MidiMessage::song_position(0).raw_status(),0xF2);
+}
@@ -1,27 +1,220 @@
-use std::io::Read;
+use std::fmt;
+use std::io::{self,Read};
+
+use byteorder::{ByteOrder, LittleEndian};
+use encoding::EncodingRef;
+use encoding::all::ISO_8859_1;
 
 use SMF;
-use ::{Event,SMFError,SMFFormat,MetaCommand,MetaEvent,MidiMessage,Track,TrackEvent};
+use ::{Event,SMFError,SMFFormat,MetaCommand,MetaEvent,MidiError,MidiMessage,Status,Track,TrackEvent};
+
+use util::{decode_text, decode_vlq, fill_buf, read_byte, read_amount, read_to_end, read_up_to, skip_amount};
+
+/// A problem in an SMF that was worked around when reading it with
+/// `SMFReader::read_smf_lenient` or `SMFReader::read_smf_with_options`
+#[derive(Debug,Clone,PartialEq)]
+pub enum SMFWarning {
+    /// The length in the header of track `track` was `declared`
+    /// bytes, but its EndOfTrack event ended after `actual` bytes
+    TrackLengthMismatch { track: usize, declared: usize, actual: usize },
+    /// `count` bytes had to be skipped to find the start of track `track`
+    SkippedBytes { track: usize, count: usize },
+    /// The header said the file has `declared` tracks, but `actual`
+    /// tracks were found
+    TrackCountMismatch { declared: usize, actual: usize },
+    /// An undefined system status byte `status` was left out of track
+    /// `track`
+    SkippedUndefinedStatus { track: usize, status: u8 },
+    /// Track `track` has `count` events after its first EndOfTrack
+    /// event
+    EventsAfterEndOfTrack { track: usize, count: usize },
+}
+
+/// The information in the header of an SMF, as returned by
+/// `SMFReader::read_header`
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct SMFMeta {
+    /// The format of the SMF
+    pub format: SMFFormat,
+    /// The number of tracks the header says the SMF contains
+    pub num_tracks: usize,
+    /// The division of the SMF, see `SMF::division`
+    pub division: i16,
+}
+
+/// What `SMFReader::read_smf_partial` returns: the SMF with the
+/// tracks that could be read, the index and error of each track that
+/// couldn't, and a warning for any garbage that was skipped
+pub type PartialSMF = (SMF,Vec<(usize,SMFError)>,Vec<SMFWarning>);
+
+/// What to do when a track contains one of the undefined system
+/// status bytes (0xF4, 0xF5, 0xF9 or 0xFD), which some devices emit.
+/// The status byte is assumed not to have any data bytes.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum UndefinedStatus {
+    /// Fail with `MidiError::InvalidStatus`
+    Error,
+    /// Leave the status out of the track, adding a warning
+    Skip,
+    /// Keep it in the track as a midi message holding just the
+    /// status byte
+    Keep,
+}
+
+/// What to do with events that come after the first EndOfTrack event
+/// of a track
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum AfterEndOfTrack {
+    /// Keep them in the track
+    Keep,
+    /// Keep them in the track, adding a warning
+    Warn,
+    /// Treat the first EndOfTrack as the end of the track, leaving
+    /// the events after it out and adding a warning
+    Drop,
+}
+
+/// Options for `SMFReader::read_smf_with_options`
+#[derive(Clone,Copy)]
+pub struct ReadOptions {
+    /// Work around bad track lengths and garbage between tracks, see
+    /// `SMFReader::read_smf_lenient`
+    pub lenient: bool,
+    /// What to do with undefined system status bytes
+    pub undefined_status: UndefinedStatus,
+    /// What to do with events after the first EndOfTrack event in a
+    /// track
+    pub after_end_of_track: AfterEndOfTrack,
+    /// The encoding used to decode the copyright, name and instrument
+    /// of each track, e.g. `encoding::all::WINDOWS_31J` for Shift-JIS
+    pub encoding: EncodingRef,
+}
+
+impl Default for ReadOptions {
+    fn default() -> ReadOptions {
+        ReadOptions {
+            lenient: false,
+            undefined_status: UndefinedStatus::Error,
+            after_end_of_track: AfterEndOfTrack::Keep,
+            encoding: ISO_8859_1,
+        }
+    }
+}
 
-use util::{fill_buf, read_byte, latin1_decode};
+impl fmt::Debug for ReadOptions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadOptions")
+            .field("lenient",&self.lenient)
+            .field("undefined_status",&self.undefined_status)
+            .field("after_end_of_track",&self.after_end_of_track)
+            .field("encoding",&self.encoding.name())
+            .finish()
+    }
+}
 
-/// An SMFReader can parse a byte stream into an SMF
+// encodings are compared by name
+impl PartialEq for ReadOptions {
+    fn eq(&self, other: &ReadOptions) -> bool {
+        self.lenient == other.lenient
+            && self.undefined_status == other.undefined_status
+            && self.after_end_of_track == other.after_end_of_track
+            && self.encoding.name() == other.encoding.name()
+    }
+}
+
+/// An SMFReader can parse a byte stream into an SMF.  Reads that
+/// would block (e.g. from a non-blocking socket) are retried, which
+/// busy-waits until data arrives.
 #[derive(Clone,Copy)]
 pub struct SMFReader;
 
+const TRACK_MAGIC: [u8;4] = [0x4D,0x54,0x72,0x6B]; // "MTrk"
+const RIFF_MAGIC: [u8;4] = [0x52,0x49,0x46,0x46]; // "RIFF"
+const RMID_MAGIC: [u8;4] = [0x52,0x4D,0x49,0x44]; // "RMID"
+const DATA_MAGIC: [u8;4] = [0x64,0x61,0x74,0x61]; // "data"
+
+// A reader that can look at the bytes ahead of it without reading
+// them, so a lenient read can tell where a track really ends
+struct Lookahead<'a> {
+    reader: &'a mut dyn Read,
+    // bytes that have been looked at but not read yet
+    ahead: Vec<u8>,
+}
+
+impl<'a> Lookahead<'a> {
+    fn new(reader: &'a mut dyn Read) -> Lookahead<'a> {
+        Lookahead {
+            reader,
+            ahead: Vec::new(),
+        }
+    }
+
+    // The next `amt` bytes, or fewer if the reader ends first
+    fn peek(&mut self, amt: usize) -> Result<&[u8],io::Error> {
+        if self.ahead.len() < amt {
+            let mut buf = vec![0; amt - self.ahead.len()];
+            let read = read_up_to(self.reader,&mut buf)?;
+            self.ahead.extend_from_slice(&buf[..read]);
+        }
+        Ok(&self.ahead[..amt.min(self.ahead.len())])
+    }
+}
+
+impl<'a> Read for Lookahead<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize,io::Error> {
+        if self.ahead.is_empty() {
+            return self.reader.read(buf);
+        }
+        let amt = buf.len().min(self.ahead.len());
+        buf[..amt].copy_from_slice(&self.ahead[..amt]);
+        self.ahead.drain(..amt);
+        Ok(amt)
+    }
+}
+
 impl SMFReader {
-    fn parse_header(reader: &mut dyn Read) -> Result<SMF,SMFError> {
-        let mut header:[u8;14] = [0;14];
-        fill_buf(reader,&mut header)?;
+    // Skip the RIFF container of an RMID file, leaving the reader at
+    // the start of the embedded SMF.  `magic` holds the four bytes
+    // that have been read already.  Returns the size of the SMF, or
+    // None if the data isn't in a RIFF container.
+    fn skip_riff(reader: &mut dyn Read, magic: &[u8;4]) -> Result<Option<u64>,SMFError> {
+        if *magic != RIFF_MAGIC {
+            return Ok(None);
+        }
+        let mut size = [0;4];
+        fill_buf(reader,&mut size)?;
+        let mut id = [0;4];
+        fill_buf(reader,&mut id)?;
+        if id != RMID_MAGIC {
+            return Err(SMFError::InvalidSMFFile("RIFF file is not of type RMID"));
+        }
+        loop {
+            fill_buf(reader,&mut id)?;
+            fill_buf(reader,&mut size)?;
+            let size = LittleEndian::read_u32(&size) as u64;
+            if id == DATA_MAGIC {
+                return Ok(Some(size));
+            }
+            // chunks are padded to an even size
+            let padded = size + (size & 1);
+            if skip_amount(reader,padded)? != padded {
+                return Err(SMFError::InvalidSMFFile("RIFF file ends before its data chunk"));
+            }
+        }
+    }
 
-        // skip RIFF header if present
-        if header[0] == 0x52 &&
-           header[1] == 0x49 &&
-           header[2] == 0x46 &&
-           header[3] == 0x46 {
-            let mut skip:[u8; 6] = [0; 6];
-            fill_buf(reader, &mut skip)?;
-            fill_buf(reader, &mut header)?;
+    // Parse the header of an SMF, or of an RMID file.  Along with the
+    // header this returns the number of bytes of tracks following it
+    // if it's known, which is the case for RMID files
+    fn parse_header(reader: &mut dyn Read) -> Result<(SMFMeta,Option<u64>),SMFError> {
+        let mut header:[u8;14] = [0;14];
+        fill_buf(reader,&mut header[..4])?;
+        let mut magic = [0;4];
+        magic.copy_from_slice(&header[..4]);
+        let data_size = SMFReader::skip_riff(reader,&magic)?;
+        match data_size {
+            Some(_) => fill_buf(reader,&mut header)?,
+            None => fill_buf(reader,&mut header[4..])?,
         }
 
         if header[0] != 0x4D ||
@@ -40,9 +233,19 @@ impl SMFReader {
         let tracks = (header[10] as u16) << 8 | header[11] as u16;
         let division = (header[12] as i16) << 8 | header[13] as i16;
 
-        Ok(SMF { format: format,
-                 tracks: Vec::with_capacity(tracks as usize),
-                 division: division } )
+        let meta = SMFMeta { format,
+                             num_tracks: tracks as usize,
+                             division };
+        Ok((meta,data_size.map(|size| size.saturating_sub(14))))
+    }
+
+    // An SMF with the format and division from `meta`, and no tracks yet
+    fn empty_smf(meta: &SMFMeta) -> SMF {
+        SMF {
+            format: meta.format,
+            tracks: Vec::with_capacity(meta.num_tracks),
+            division: meta.division,
+        }
     }
 
     fn next_event(reader: &mut dyn Read, laststat: u8, was_running: &mut bool) -> Result<TrackEvent,SMFError> {
@@ -63,6 +266,20 @@ impl SMFReader {
                     event: Event::Meta(event),
                 })
             }
+            0xF0 | 0xF7 => {
+                let msg = SMFReader::next_sysex(stat,reader)?;
+                Ok( TrackEvent {
+                    vtime: time,
+                    event: Event::Midi(msg),
+                })
+            }
+            _ if Status::from_byte(stat) == Status::Undefined && (stat & 0x80) != 0 => {
+                // left to the caller to decide what to do with
+                Ok( TrackEvent {
+                    vtime: time,
+                    event: Event::Midi(MidiMessage::from_bytes(vec![stat])),
+                })
+            }
             _ => {
                 let msg =
                     if (stat & 0x80) == 0 {
@@ -79,48 +296,117 @@ impl SMFReader {
         }
     }
 
-    fn parse_track(reader: &mut dyn Read) -> Result<Track,SMFError> {
-        let mut res:Vec<TrackEvent> = Vec::new();
+    // SysEx events in an SMF are stored as the status byte followed by
+    // a variable length value giving the number of bytes that follow
+    // (including the terminating SysExEnd).  Escape events (0xF7) use
+    // the same framing, and are used to continue a SysEx that was
+    // split into several packets
+    fn next_sysex(stat: u8, reader: &mut dyn Read) -> Result<MidiMessage,SMFError> {
+        let len = SMFReader::read_vtime(reader)?;
+        let mut data = vec![stat];
+        read_amount(reader,&mut data,len as usize)?;
+        Ok(MidiMessage::from_bytes(data))
+    }
+
+    // Shift bytes through `buf` until it holds the track magic.
+    // Returns the number of bytes that were skipped
+    fn skip_to_track_magic(reader: &mut dyn Read, buf: &mut [u8;4]) -> Result<usize,SMFError> {
+        let mut skipped = 0;
+        while *buf != TRACK_MAGIC {
+            let mut next = [0;1];
+            fill_buf(reader,&mut next)?;
+            buf[0] = buf[1];
+            buf[1] = buf[2];
+            buf[2] = buf[3];
+            buf[3] = next[0];
+            skipped += 1;
+        }
+        Ok(skipped)
+    }
+
+    // Read the magic and length of track number `track`, returning
+    // the length, or None if the reader is at its end.  If `lenient`
+    // is set, garbage before the track is skipped and a warning added
+    // to `warnings`.  If `required` isn't set (for tracks past the
+    // number in the header) a chunk that isn't a track also gives None.
+    fn read_track_header(reader: &mut dyn Read, track: usize, lenient: bool, required: bool,
+                         warnings: &mut Vec<SMFWarning>) -> Result<Option<usize>,SMFError> {
         let mut buf:[u8;4] = [0;4];
+        let read = read_up_to(reader,&mut buf)?;
+        if read == 0 {
+            return Ok(None);
+        }
+        // fails if the reader ended part way through the magic
+        fill_buf(reader,&mut buf[read..])?;
+        if buf != TRACK_MAGIC {
+            if !required {
+                return Ok(None);
+            }
+            if !lenient {
+                return Err(SMFError::InvalidSMFFile("Invalid track magic"));
+            }
+            let count = SMFReader::skip_to_track_magic(reader,&mut buf)?;
+            warnings.push(SMFWarning::SkippedBytes { track, count });
+        }
+        fill_buf(reader,&mut buf)?;
+        Ok(Some(((buf[0] as u32) << 24 |
+                 (buf[1] as u32) << 16 |
+                 (buf[2] as u32) << 8 |
+                 (buf[3] as u32)) as usize))
+    }
+
+    // Parse track number `track`, returning None if there are no more
+    // tracks (see `read_track_header`).  If `options.lenient` is set,
+    // garbage before the track is skipped, and a track whose length
+    // doesn't match its data is read up to its EndOfTrack event.
+    // Anything that was worked around is added to `warnings`.
+    fn parse_track(reader: &mut Lookahead, track: usize, options: &ReadOptions, required: bool,
+                   warnings: &mut Vec<SMFWarning>) -> Result<Option<Track>,SMFError> {
+        let lenient = options.lenient;
+        // time of skipped events, to add to the next event
+        let mut carry = 0;
+        let mut res:Vec<TrackEvent> = Vec::new();
 
         let mut copyright = None;
         let mut name = None;
+        let mut instrument = None;
 
-        fill_buf(reader,&mut buf)?;
-        if buf[0] != 0x4D || // "MTrk"
-           buf[1] != 0x54 ||
-           buf[2] != 0x72 ||
-           buf[3] != 0x6B {
-               return Err(SMFError::InvalidSMFFile("Invalid track magic"));
-           }
-        fill_buf(reader,&mut buf)?;
-        let len =
-            ((buf[0] as u32) << 24 |
-             (buf[1] as u32) << 16 |
-             (buf[2] as u32) << 8 |
-             (buf[3] as u32)) as usize;
-
+        let len = match SMFReader::read_track_header(reader,track,lenient,required,warnings)? {
+            Some(len) => len,
+            None => return Ok(None),
+        };
         let mut read_so_far = 0;
+        // status from last midi event, skipping meta and real-time events
+        let mut last = 0u8;
+        let mut saw_eot = false;
+        let mut after_eot = 0;
 
         loop {
-            let last = { // use status from last midi event, skip meta events
-                let mut last = 0u8;
-                for e in res.iter().rev() {
-                    match e.event {
-                        Event::Midi(ref m) => { last = m.data[0]; break; }
-                        _ => ()
-                    }
-                }
-                last
-            };
             let mut was_running = false;
             match SMFReader::next_event(reader,last,&mut was_running) {
-                Ok(event) => {
+                Ok(mut event) => {
+                    let undefined = match event.event {
+                        Event::Midi(ref m) if is_undefined(m) => Some(m.raw_status()),
+                        _ => None,
+                    };
+                    if let Event::Midi(ref m) = event.event {
+                        if undefined.is_none() && !m.is_realtime() {
+                            // SysEx and system common messages cancel the running status
+                            let status = m.raw_status();
+                            last = if status < 0xF0 { status } else { 0 };
+                        }
+                    }
                     match event.event {
                         Event::Meta(ref me) => {
-                            match me.command {
-                                MetaCommand::CopyrightNotice => copyright = Some(latin1_decode(&me.data)),
-                                MetaCommand::SequenceOrTrackName => name = Some(latin1_decode(&me.data)),
+                            // the first of each one in the track is used
+                            let field = match me.command {
+                                MetaCommand::CopyrightNotice => Some(&mut copyright),
+                                MetaCommand::SequenceOrTrackName => Some(&mut name),
+                                MetaCommand::InstrumentName => Some(&mut instrument),
+                                _ => None,
+                            };
+                            match field {
+                                Some(field) if field.is_none() => *field = Some(decode_text(&me.data,options.encoding)),
                                 _ => {}
                             }
                         },
@@ -131,11 +417,47 @@ impl SMFReader {
                         // used a running status, so didn't actually read a status byte
                         read_so_far -= 1;
                     }
-                    res.push(event);
+                    let is_eot = match event.event {
+                        Event::Meta(ref me) => me.command == MetaCommand::EndOfTrack,
+                        _ => false,
+                    };
+                    event.vtime += carry;
+                    carry = 0;
+                    match (undefined,options.undefined_status) {
+                        (Some(status),UndefinedStatus::Error) => {
+                            return Err(SMFError::MidiError(MidiError::InvalidStatus(status)));
+                        }
+                        (Some(status),UndefinedStatus::Skip) => {
+                            warnings.push(SMFWarning::SkippedUndefinedStatus { track, status });
+                            carry = event.vtime;
+                        }
+                        _ if saw_eot => {
+                            after_eot += 1;
+                            if options.after_end_of_track != AfterEndOfTrack::Drop {
+                                res.push(event);
+                            }
+                        }
+                        _ => res.push(event),
+                    }
+                    saw_eot |= is_eot;
                     if read_so_far == len {
                         break;
                     }
-                    if read_so_far > len {
+                    // the declared length is wrong if it ends before the
+                    // EndOfTrack, or if the next track or the end of the
+                    // data comes before it
+                    if lenient && is_eot && (read_so_far > len || {
+                        let ahead = reader.peek(4)?;
+                        ahead.len() < (len - read_so_far).min(4) || ahead == TRACK_MAGIC
+                    }) {
+                        warnings.push(SMFWarning::TrackLengthMismatch {
+                            track,
+                            declared: len,
+                            actual: read_so_far,
+                        });
+                        break;
+                    }
+                    if read_so_far > len && !lenient {
                         return Err(SMFError::InvalidSMFFile("Invalid MIDI file"));
                     }
                 },
@@ -155,46 +477,803 @@ impl SMFReader {
                 }
             }
         }
-        Ok(Track {
-            copyright: copyright,
-            name: name,
+        if after_eot > 0 && options.after_end_of_track != AfterEndOfTrack::Keep {
+            warnings.push(SMFWarning::EventsAfterEndOfTrack { track, count: after_eot });
+        }
+        Ok(Some(Track {
+            copyright,
+            name,
+            instrument,
             events: res
-        })
+        }))
+    }
+
+    // Read all the tracks following the header, which declared
+    // `declared` tracks.  Reading stops at the end of the reader, even
+    // if there should be more tracks, and continues past the declared
+    // number for as long as more tracks follow.
+    fn read_tracks(reader: &mut dyn Read, smf: &mut SMF, declared: usize, options: &ReadOptions,
+                   warnings: &mut Vec<SMFWarning>) -> Result<(),SMFError> {
+        let mut reader = Lookahead::new(reader);
+        loop {
+            let i = smf.tracks.len();
+            match SMFReader::parse_track(&mut reader,i,options,i < declared,warnings)? {
+                Some(track) => smf.tracks.push(track),
+                None => break,
+            }
+        }
+        if options.lenient && smf.tracks.len() != declared {
+            warnings.push(SMFWarning::TrackCountMismatch {
+                declared,
+                actual: smf.tracks.len(),
+            });
+        }
+        Ok(())
+    }
+
+    // Read the tracks, only reading `size` bytes if it is known, so
+    // any chunks after the SMF in an RMID file are ignored
+    fn read_sized_tracks(reader: &mut dyn Read, size: Option<u64>, smf: &mut SMF, declared: usize, options: &ReadOptions,
+                         warnings: &mut Vec<SMFWarning>) -> Result<(),SMFError> {
+        match size {
+            Some(size) => SMFReader::read_tracks(&mut reader.take(size),smf,declared,options,warnings),
+            None => SMFReader::read_tracks(reader,smf,declared,options,warnings),
+        }
     }
 
     /// Read a variable sized value from the reader.
     /// This is usually used for the times of midi events but is used elsewhere as well.
     pub fn read_vtime(reader: &mut dyn Read) -> Result<u64,SMFError> {
-        let mut res: u64 = 0;
-        let mut i = 0;
-        let cont_mask = 0x80;
-        let val_mask = 0x7F;
-        loop {
-            i+=1;
-            if i > 9 {
-                return Err(SMFError::InvalidSMFFile("Variable length value too long"));
+        Ok(decode_vlq(reader)?)
+    }
+
+    /// Read from `reader` until just after the next track magic
+    /// ("MTrk"), so the length of the track is the next thing to be
+    /// read.  This can be used to carry on reading after a corrupt
+    /// track.  Returns an error if the reader ends first.
+    pub fn resync_to_next_track(reader: &mut dyn Read) -> Result<(),SMFError> {
+        let mut buf = [0;4];
+        fill_buf(reader,&mut buf)?;
+        SMFReader::skip_to_track_magic(reader,&mut buf)?;
+        Ok(())
+    }
+
+    /// Read only the header of an SMF (or RMID) file, without
+    /// parsing any of the tracks.  The reader is left at the start of
+    /// the first track.
+    pub fn read_header(reader: &mut dyn Read) -> Result<SMFMeta,SMFError> {
+        let (meta,_) = SMFReader::parse_header(reader)?;
+        Ok(meta)
+    }
+
+    /// Read an entire SMF file
+    pub fn read_smf(reader: &mut dyn Read) -> Result<SMF,SMFError> {
+        let (smf,_) = SMFReader::read_smf_with_options(reader,&ReadOptions::default())?;
+        Ok(smf)
+    }
+
+    /// Read an entire SMF file, working around some common problems.
+    /// If the length of a track doesn't match its contents, the track
+    /// is read up to its EndOfTrack event, and then the reader skips
+    /// ahead to the start of the next track.  Returns the SMF along
+    /// with a warning for each problem that was found.
+    pub fn read_smf_lenient(reader: &mut dyn Read) -> Result<(SMF,Vec<SMFWarning>),SMFError> {
+        let options = ReadOptions {
+            lenient: true,
+            ..ReadOptions::default()
+        };
+        SMFReader::read_smf_with_options(reader,&options)
+    }
+
+    /// Read an entire SMF file, decoding the copyright, name and
+    /// instrument of each track with `encoding` instead of latin1
+    pub fn read_smf_with_encoding(reader: &mut dyn Read, encoding: EncodingRef) -> Result<SMF,SMFError> {
+        let options = ReadOptions {
+            encoding,
+            ..ReadOptions::default()
+        };
+        let (smf,_) = SMFReader::read_smf_with_options(reader,&options)?;
+        Ok(smf)
+    }
+
+    /// Read an entire SMF file with the given options.  Returns the
+    /// SMF along with a warning for each problem that was worked
+    /// around.
+    pub fn read_smf_with_options(reader: &mut dyn Read, options: &ReadOptions) -> Result<(SMF,Vec<SMFWarning>),SMFError> {
+        let (meta,size) = SMFReader::parse_header(reader)?;
+        let mut smf = SMFReader::empty_smf(&meta);
+        let mut warnings = Vec::new();
+        SMFReader::read_sized_tracks(reader,size,&mut smf,meta.num_tracks,options,&mut warnings)?;
+        Ok((smf,warnings))
+    }
+
+    /// Read as much of an SMF file as possible.  A track that can't
+    /// be parsed is left out, and reading carries on from the next
+    /// track magic ("MTrk") after it.  Returns the SMF with all the
+    /// tracks that could be read, along with the error for each one
+    /// that couldn't and the index of that track in the file (which
+    /// counts the tracks that were left out).  Garbage between tracks
+    /// isn't a track, so it's reported as a `SkippedBytes` warning for
+    /// the track after it.  An invalid header is still an error, since
+    /// nothing can be read without it.
+    pub fn read_smf_partial(reader: &mut dyn Read) -> Result<PartialSMF,SMFError> {
+        let (meta,size) = SMFReader::parse_header(reader)?;
+        let mut smf = SMFReader::empty_smf(&meta);
+        let mut data = Vec::new();
+        match size {
+            Some(size) => read_to_end(&mut reader.take(size),&mut data)?,
+            None => read_to_end(reader,&mut data)?,
+        }
+        // position of the next track magic at or after `from`, if any
+        let find_magic = |from: usize| {
+            let mut rest = data.get(from..).unwrap_or(&[]);
+            SMFReader::resync_to_next_track(&mut rest).ok().map(|()| data.len() - rest.len() - 4)
+        };
+
+        let mut errors = Vec::new();
+        let mut warnings = Vec::new();
+        let mut pos = 0;
+        // index of the track being read
+        let mut track = 0;
+        while pos < data.len() {
+            if !data[pos..].starts_with(&TRACK_MAGIC) {
+                match find_magic(pos) {
+                    Some(next) => {
+                        warnings.push(SMFWarning::SkippedBytes { track, count: next - pos });
+                        pos = next;
+                    }
+                    None => break,
+                }
             }
-            let next = read_byte(reader)?;
-            res |= next as u64 & val_mask;
-            if (next & cont_mask) == 0 {
-                break;
+            let len = data.get(pos+4..pos+8).map_or(0,|len| {
+                (len[0] as usize) << 24 | (len[1] as usize) << 16 | (len[2] as usize) << 8 | len[3] as usize
+            });
+            let end = (pos + 8).saturating_add(len).min(data.len());
+            let mut chunk = &data[pos..end];
+            match SMFReader::parse_track(&mut Lookahead::new(&mut chunk),track,&ReadOptions::default(),true,&mut warnings) {
+                Ok(Some(parsed)) => {
+                    smf.tracks.push(parsed);
+                    pos = end;
+                }
+                Ok(None) => break,
+                Err(err) => {
+                    errors.push((track,err));
+                    pos = find_magic(pos + 4).unwrap_or(data.len());
+                }
             }
-            res = res << 7;
+            track += 1;
         }
-        Ok(res)
+        Ok((smf,errors,warnings))
     }
+}
 
-    /// Read an entire SMF file
-    pub fn read_smf(reader: &mut dyn Read) -> Result<SMF,SMFError> {
-        let mut smf = SMFReader::parse_header(reader);
-        match smf {
-            Ok(ref mut s) => {
-                for _ in 0..s.tracks.capacity() {
-                    s.tracks.push(SMFReader::parse_track(reader)?);
+// true for the messages `SMFReader::next_event` makes for undefined
+// status bytes
+fn is_undefined(msg: &MidiMessage) -> bool {
+    msg.data.len() == 1 && msg.status() == Status::Undefined
+}
+
+/// An SMFStreamReader reads the events of an SMF one at a time,
+/// without ever holding a whole track in memory.  It iterates over
+/// the index of the track each event is in, along with the event.
+/// Iteration stops after the first error.
+pub struct SMFStreamReader<R: Read> {
+    // limited to the data chunk for RMID files, so any chunks after
+    // it aren't read as tracks
+    reader: io::Take<R>,
+    format: SMFFormat,
+    division: i16,
+    num_tracks: usize,
+    // the track currently being read
+    track: usize,
+    // bytes left in the current track, None if the next track's
+    // header hasn't been read yet
+    remaining: Option<usize>,
+    // status of the last midi message in the current track
+    last_status: u8,
+    done: bool,
+}
+
+impl<R: Read> SMFStreamReader<R> {
+    /// Create a new stream reader, reading the SMF header from `reader`
+    pub fn new(mut reader: R) -> Result<SMFStreamReader<R>,SMFError> {
+        let (meta,size) = SMFReader::parse_header(&mut reader)?;
+        Ok(SMFStreamReader {
+            reader: reader.take(size.unwrap_or(u64::MAX)),
+            format: meta.format,
+            division: meta.division,
+            num_tracks: meta.num_tracks,
+            track: 0,
+            remaining: None,
+            last_status: 0,
+            done: false,
+        })
+    }
+
+    /// The format of the SMF being read
+    pub fn format(&self) -> SMFFormat {
+        self.format
+    }
+
+    /// The division of the SMF being read, see `SMF::division`
+    pub fn division(&self) -> i16 {
+        self.division
+    }
+
+    /// The number of tracks the header of the SMF says it contains
+    pub fn num_tracks(&self) -> usize {
+        self.num_tracks
+    }
+
+    fn next_event(&mut self) -> Result<Option<(usize,TrackEvent)>,SMFError> {
+        loop {
+            match self.remaining {
+                None => {
+                    let required = self.track < self.num_tracks;
+                    match SMFReader::read_track_header(&mut self.reader,self.track,false,required,&mut Vec::new())? {
+                        Some(len) => self.remaining = Some(len),
+                        None => return Ok(None),
+                    }
+                    self.last_status = 0;
+                }
+                Some(0) => {
+                    self.track += 1;
+                    self.remaining = None;
+                }
+                Some(remaining) => {
+                    let mut was_running = false;
+                    let event = SMFReader::next_event(&mut self.reader,self.last_status,&mut was_running)?;
+                    if let Event::Midi(ref m) = event.event {
+                        if is_undefined(m) {
+                            return Err(SMFError::MidiError(MidiError::InvalidStatus(m.data[0])));
+                        }
+                    }
+                    let mut len = event.len();
+                    if was_running {
+                        // used a running status, so didn't actually read a status byte
+                        len -= 1;
+                    }
+                    if len > remaining {
+                        return Err(SMFError::InvalidSMFFile("Invalid MIDI file"));
+                    }
+                    self.remaining = Some(remaining - len);
+                    if let Event::Midi(ref m) = event.event {
+                        if !m.is_realtime() {
+                            // SysEx and system common messages cancel the running status
+                            let status = m.raw_status();
+                            self.last_status = if status < 0xF0 { status } else { 0 };
+                        }
+                    }
+                    return Ok(Some((self.track,event)));
                 }
             }
-            _ => {}
         }
-        smf
     }
 }
+
+impl<R: Read> Iterator for SMFStreamReader<R> {
+    type Item = Result<(usize,TrackEvent),SMFError>;
+
+    fn next(&mut self) -> Option<Result<(usize,TrackEvent),SMFError>> {
+        if self.done {
+            return None;
+        }
+        match self.next_event() {
+            Ok(Some(event)) => Some(Ok(event)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+#[test]
+fn sysex_round_trip() {
+    use std::io::Cursor;
+    use ::{SMFBuilder,SMFWriter};
+
+    let sysex = MidiMessage::from_bytes(vec![0xF0,0x43,0x12,0x00,0x01,0x02,0xF7]);
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_midi_abs(0,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(0,10,sysex.clone()).unwrap();
+    builder.add_midi_abs(0,20,MidiMessage::note_off(60,0,0)).unwrap();
+
+    let mut bytes = Vec::new();
+    SMFWriter::from_smf(builder.result()).write_all(&mut bytes).unwrap();
+    let smf = SMFReader::read_smf(&mut Cursor::new(bytes)).unwrap();
+
+    let events = &smf.tracks[0].events;
+    assert_eq!(events.len(),4);
+    match events[1].event {
+        Event::Midi(ref m) => {
+            assert!(m.is_sysex());
+            assert_eq!(m.data,sysex.data);
+        }
+        _ => panic!("expected a sysex message"),
+    }
+    match events[2].event {
+        Event::Midi(ref m) => assert_eq!(m.data,vec![0x80,60,0]),
+        _ => panic!("expected a note off"),
+    }
+}
+
+#[test]
+fn split_sysex() {
+    use std::io::Cursor;
+    use ::SMFWriter;
+
+    let bytes = vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,0x60,
+                     0x4D,0x54,0x72,0x6B,0,0,0,17,
+                     0x00,0xF0,0x03,0x43,0x12,0x00,
+                     0x81,0x48,0xF7,0x03,0x01,0x02,0xF7,
+                     0x00,0xFF,0x2F,0x00];
+    let smf = SMFReader::read_smf(&mut Cursor::new(bytes.clone())).unwrap();
+
+    let events = &smf.tracks[0].events;
+    assert_eq!(events.len(),3);
+    match events[0].event {
+        Event::Midi(ref m) => {
+            assert!(m.is_sysex());
+            assert_eq!(m.data,vec![0xF0,0x43,0x12,0x00]);
+        }
+        _ => panic!("expected a sysex message"),
+    }
+    assert_eq!(events[1].vtime,200);
+    match events[1].event {
+        Event::Midi(ref m) => {
+            assert!(m.is_sysex_escape());
+            assert_eq!(m.data,vec![0xF7,0x01,0x02,0xF7]);
+        }
+        _ => panic!("expected a sysex continuation"),
+    }
+
+    let mut written = Vec::new();
+    SMFWriter::from_smf(smf).write_all(&mut written).unwrap();
+    assert_eq!(written,bytes);
+}
+
+#[test]
+fn lenient_track_length() {
+    use std::io::Cursor;
+
+    let file = |first_len: u8| {
+        vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0,2,0,0x60,
+             0x4D,0x54,0x72,0x6B,0,0,0,first_len,
+             0x00,0x90,0x3C,0x64,
+             0x60,0x80,0x3C,0x00,
+             0x00,0xFF,0x2F,0x00,
+             0x4D,0x54,0x72,0x6B,0,0,0,7,
+             0x00,0xC0,0x05,
+             0x00,0xFF,0x2F,0x00]
+    };
+
+    // declared length is too short
+    assert!(SMFReader::read_smf(&mut Cursor::new(file(10))).is_err());
+    let (smf,warnings) = SMFReader::read_smf_lenient(&mut Cursor::new(file(10))).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    assert_eq!(smf.tracks[0].events.len(),3);
+    assert_eq!(smf.tracks[1].events.len(),2);
+    assert_eq!(warnings,vec![SMFWarning::TrackLengthMismatch { track: 0, declared: 10, actual: 12 }]);
+
+    // declared length is too long
+    let (smf,warnings) = SMFReader::read_smf_lenient(&mut Cursor::new(file(14))).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    assert_eq!(smf.tracks[1].events.len(),2);
+    assert_eq!(warnings,vec![SMFWarning::TrackLengthMismatch { track: 0, declared: 14, actual: 12 }]);
+}
+
+#[test]
+fn stream_events() {
+    use std::io::Cursor;
+    use ::{SMFBuilder,SMFWriter};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_track();
+    builder.add_meta_abs(0,0,MetaEvent::tempo_setting(500000)).unwrap();
+    builder.add_midi_abs(1,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(1,96,MidiMessage::note_on(60,0,0)).unwrap();
+    builder.add_midi_abs(1,96,MidiMessage::note_on(62,100,0)).unwrap();
+    builder.add_midi_abs(1,192,MidiMessage::note_on(62,0,0)).unwrap();
+    let mut writer = SMFWriter::new_with_division(96);
+    writer.set_running_status(true);
+    for track in builder.result().tracks.iter() {
+        writer.add_smf_track(track).unwrap();
+    }
+    let mut bytes = Vec::new();
+    writer.write_all(&mut bytes).unwrap();
+
+    let stream = SMFStreamReader::new(Cursor::new(bytes)).unwrap();
+    assert_eq!(stream.num_tracks(),2);
+    let mut counts = [0;2];
+    for res in stream {
+        let (track,_) = res.unwrap();
+        counts[track] += 1;
+    }
+    // each track gets an EndOfTrack added by the writer
+    assert_eq!(counts,[2,5]);
+}
+
+#[test]
+fn track_count_mismatch() {
+    let header = |tracks: u8| vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0,tracks,0,96];
+    let track = [0x4D,0x54,0x72,0x6B,0,0,0,8,
+                 0x00,0x90,0x3C,0x64,
+                 0x00,0xFF,0x2F,0x00];
+
+    // header claims three tracks but only two follow
+    let mut bytes = header(3);
+    bytes.extend_from_slice(&track);
+    bytes.extend_from_slice(&track);
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    let (smf,warnings) = SMFReader::read_smf_lenient(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    assert_eq!(warnings,vec![SMFWarning::TrackCountMismatch { declared: 3, actual: 2 }]);
+    let events: Vec<_> = SMFStreamReader::new(&bytes[..]).unwrap().collect();
+    assert_eq!(events.len(),4);
+    assert!(events.iter().all(|e| e.is_ok()));
+
+    // header claims one track but three follow, then a non-track chunk
+    let mut bytes = header(1);
+    bytes.extend_from_slice(&track);
+    bytes.extend_from_slice(&track);
+    bytes.extend_from_slice(&track);
+    bytes.extend_from_slice(&[0x4A,0x55,0x4E,0x4B,0,0,0,0]);
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks.len(),3);
+    let (_,warnings) = SMFReader::read_smf_lenient(&mut &bytes[..]).unwrap();
+    assert_eq!(warnings,vec![SMFWarning::TrackCountMismatch { declared: 1, actual: 3 }]);
+    let tracks: Vec<usize> = SMFStreamReader::new(&bytes[..]).unwrap().map(|e| e.unwrap().0).collect();
+    assert_eq!(tracks,vec![0,0,1,1,2,2]);
+
+    // a truncated track is still an error
+    let mut bytes = header(1);
+    bytes.extend_from_slice(&track[..10]);
+    assert!(SMFReader::read_smf(&mut &bytes[..]).is_err());
+}
+
+#[test]
+fn rmid_file() {
+    let smf = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+               0x4D,0x54,0x72,0x6B,0,0,0,8,
+               0x00,0x90,0x3C,0x64,
+               0x00,0xFF,0x2F,0x00];
+    let mut bytes = vec![0x52,0x49,0x46,0x46,0,0,0,0,0x52,0x4D,0x49,0x44];
+    // a chunk before the data, with a pad byte
+    bytes.extend_from_slice(&[0x49,0x4E,0x46,0x4F,3,0,0,0,1,2,3,0]);
+    bytes.extend_from_slice(&[0x64,0x61,0x74,0x61,smf.len() as u8,0,0,0]);
+    bytes.extend_from_slice(&smf);
+    // trailing DLS chunk, which also starts with "MTrk" to check it isn't read as a track
+    bytes.extend_from_slice(&[0x4D,0x54,0x72,0x6B,0,0,0,1,0]);
+    let size = (bytes.len() - 8) as u8;
+    bytes[4] = size;
+
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.division,96);
+    assert_eq!(smf.tracks.len(),1);
+    assert_eq!(smf.tracks[0].events.len(),2);
+
+    let streamed: Vec<_> = SMFStreamReader::new(&bytes[..]).unwrap().collect();
+    assert_eq!(streamed.len(),2);
+    assert!(streamed.iter().all(|event| event.is_ok()));
+
+    // like a non-blocking socket that only has data every other read
+    struct Blocking<'a> { data: &'a [u8], blocked: bool }
+    impl<'a> Read for Blocking<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.blocked = !self.blocked;
+            if self.blocked {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "would block"));
+            }
+            self.data.read(&mut buf[..1])
+        }
+    }
+    let smf = SMFReader::read_smf(&mut Blocking { data: &bytes[..], blocked: false }).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),2);
+    let (smf,errors,_) = SMFReader::read_smf_partial(&mut Blocking { data: &bytes[..], blocked: false }).unwrap();
+    assert_eq!(smf.tracks.len(),1);
+    assert!(errors.is_empty());
+
+    // other RIFF forms are rejected
+    bytes[8..12].copy_from_slice(&[0x57,0x41,0x56,0x45]);
+    assert!(SMFReader::read_smf(&mut &bytes[..]).is_err());
+}
+
+#[test]
+fn header_only() {
+    // counts the bytes read through it
+    struct Counting<'a> { data: &'a [u8], read: usize }
+    impl<'a> Read for Counting<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.data.read(buf)?;
+            self.read += n;
+            Ok(n)
+        }
+    }
+
+    let mut bytes = vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0x01,0x00,0x01,0xE0];
+    for _ in 0..256 {
+        bytes.extend_from_slice(&[0x4D,0x54,0x72,0x6B,0,0,0x10,0]);
+        bytes.extend_from_slice(&[0;0x1000]);
+    }
+    let mut reader = Counting { data: &bytes[..], read: 0 };
+    let meta = SMFReader::read_header(&mut reader).unwrap();
+    assert_eq!(meta,SMFMeta { format: SMFFormat::MultiTrack, num_tracks: 256, division: 480 });
+    assert_eq!(reader.read,14);
+}
+
+#[test]
+fn undefined_status() {
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,14,
+                 0x00,0x90,0x3C,0x64,
+                 0x05,0xF5,
+                 0x0A,0x80,0x3C,0x00,
+                 0x00,0xFF,0x2F,0x00];
+
+    match SMFReader::read_smf(&mut &bytes[..]) {
+        Err(SMFError::MidiError(MidiError::InvalidStatus(0xF5))) => {}
+        other => panic!("expected an invalid status error, got {:?}",other),
+    }
+
+    let options = ReadOptions { undefined_status: UndefinedStatus::Skip, ..ReadOptions::default() };
+    let (smf,warnings) = SMFReader::read_smf_with_options(&mut &bytes[..],&options).unwrap();
+    assert_eq!(warnings,vec![SMFWarning::SkippedUndefinedStatus { track: 0, status: 0xF5 }]);
+    let events = &smf.tracks[0].events;
+    assert_eq!(events.len(),3);
+    assert_eq!(events[1].vtime,15);
+
+    let options = ReadOptions { undefined_status: UndefinedStatus::Keep, ..ReadOptions::default() };
+    let (smf,warnings) = SMFReader::read_smf_with_options(&mut &bytes[..],&options).unwrap();
+    assert!(warnings.is_empty());
+    let events = &smf.tracks[0].events;
+    assert_eq!(events.len(),4);
+    match events[1].event {
+        Event::Midi(ref m) => {
+            assert_eq!(m.data,vec![0xF5]);
+            assert_eq!(m.status(),Status::Undefined);
+            assert_eq!(m.channel(),None);
+        }
+        _ => panic!("expected a midi event"),
+    }
+    assert_eq!((events[1].vtime,events[2].vtime),(5,10));
+}
+
+#[test]
+fn realtime_messages() {
+    // a running status note off after real-time messages
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,15,
+                 0x00,0x90,0x3C,0x64,
+                 0x05,0xF8,
+                 0x00,0xFE,
+                 0x0A,0x3C,0x00,
+                 0x00,0xFF,0x2F,0x00];
+
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    let events = &smf.tracks[0].events;
+    assert_eq!(events.len(),5);
+    for &(i,status) in [(1,Status::TimingClock),(2,Status::ActiveSensing)].iter() {
+        match events[i].event {
+            Event::Midi(ref m) => {
+                assert_eq!(m.status(),status);
+                assert_eq!(m.data.len(),1);
+                assert!(m.is_realtime());
+            }
+            _ => panic!("expected a midi event"),
+        }
+    }
+    match events[3].event {
+        Event::Midi(ref m) => {
+            assert_eq!(m.data,vec![0x90,0x3C,0x00]);
+            assert!(!m.is_realtime());
+        }
+        _ => panic!("expected a midi event"),
+    }
+
+    let streamed: Vec<TrackEvent> = SMFStreamReader::new(&bytes[..]).unwrap().map(|e| e.unwrap().1).collect();
+    assert_eq!(streamed.len(),5);
+    match streamed[3].event {
+        Event::Midi(ref m) => assert_eq!(m.data,vec![0x90,0x3C,0x00]),
+        _ => panic!("expected a midi event"),
+    }
+}
+
+#[test]
+fn running_status_after_system_common() {
+    // a data byte after a tune request, and after an empty escape event
+    for &(stat,rest) in [(0xF6,&[][..]),(0xF7,&[0x00][..])].iter() {
+        let mut bytes = vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                             0x4D,0x54,0x72,0x6B,0,0,0,(10 + rest.len()) as u8,
+                             0x00,stat];
+        bytes.extend_from_slice(rest);
+        bytes.extend_from_slice(&[0x00,0x3C,0x64,
+                                  0x00,0xFF,0x2F,0x00]);
+
+        assert!(SMFReader::read_smf(&mut &bytes[..]).is_err());
+        let (smf,errors,_) = SMFReader::read_smf_partial(&mut &bytes[..]).unwrap();
+        assert!(smf.tracks.is_empty());
+        assert_eq!(errors.len(),1);
+        let streamed: Vec<_> = SMFStreamReader::new(&bytes[..]).unwrap().collect();
+        assert_eq!(streamed.len(),2);
+        assert!(streamed[1].is_err());
+    }
+}
+
+#[test]
+fn system_common_cancels_running_status() {
+    // a data byte after a song select isn't another song select
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,13,
+                 0x00,0x90,0x3C,0x64,
+                 0x00,0xF3,0x01,
+                 0x00,0x02,
+                 0x00,0xFF,0x2F,0x00];
+
+    match SMFReader::read_smf(&mut &bytes[..]) {
+        Err(SMFError::MidiError(MidiError::InvalidStatus(0))) => {}
+        other => panic!("expected an invalid status error, got {:?}",other),
+    }
+    let streamed: Vec<_> = SMFStreamReader::new(&bytes[..]).unwrap().collect();
+    assert_eq!(streamed.len(),3);
+    match streamed[2] {
+        Err(SMFError::MidiError(MidiError::InvalidStatus(0))) => {}
+        ref other => panic!("expected an invalid status error, got {:?}",other),
+    }
+}
+
+#[test]
+fn events_after_end_of_track() {
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,15,
+                 0x00,0x90,0x3C,0x64,
+                 0x00,0xFF,0x2F,0x00,
+                 0x0A,0x3C,0x00,
+                 0x00,0xFF,0x2F,0x00];
+
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),4);
+
+    let options = ReadOptions { after_end_of_track: AfterEndOfTrack::Warn, ..ReadOptions::default() };
+    let (smf,warnings) = SMFReader::read_smf_with_options(&mut &bytes[..],&options).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),4);
+    assert_eq!(warnings,vec![SMFWarning::EventsAfterEndOfTrack { track: 0, count: 2 }]);
+
+    let options = ReadOptions { after_end_of_track: AfterEndOfTrack::Drop, ..ReadOptions::default() };
+    let (smf,warnings) = SMFReader::read_smf_with_options(&mut &bytes[..],&options).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),2);
+    match smf.tracks[0].events[1].event {
+        Event::Meta(ref meta) => assert_eq!(meta.command,MetaCommand::EndOfTrack),
+        _ => panic!("expected a meta event"),
+    }
+    assert_eq!(warnings,vec![SMFWarning::EventsAfterEndOfTrack { track: 0, count: 2 }]);
+
+    // the length is right, so a lenient read doesn't stop at the first EndOfTrack
+    let options = ReadOptions { lenient: true, ..ReadOptions::default() };
+    let (smf,warnings) = SMFReader::read_smf_with_options(&mut &bytes[..],&options).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),4);
+    assert!(warnings.is_empty());
+
+    let options = ReadOptions { lenient: true, after_end_of_track: AfterEndOfTrack::Warn, ..ReadOptions::default() };
+    let (smf,warnings) = SMFReader::read_smf_with_options(&mut &bytes[..],&options).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),4);
+    assert_eq!(warnings,vec![SMFWarning::EventsAfterEndOfTrack { track: 0, count: 2 }]);
+}
+
+#[test]
+fn track_text_fields() {
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,28,
+                 0x00,0xFF,0x04,0x05,0x50,0x69,0x61,0x6E,0x6F,
+                 0x00,0xFF,0x03,0x01,0x41,
+                 0x00,0xFF,0x03,0x01,0x42,
+                 0x00,0xFF,0x04,0x01,0x43,
+                 0x00,0xFF,0x2F,0x00];
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    let track = &smf.tracks[0];
+    assert_eq!(track.instrument,Some("Piano".to_string()));
+    assert_eq!(track.name,Some("A".to_string()));
+    assert_eq!(track.copyright,None);
+    assert_eq!(track.events.len(),5);
+}
+
+#[test]
+fn shift_jis_track_name() {
+    use encoding::all::WINDOWS_31J;
+
+    // a track named "日本" in Shift-JIS
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,0,0,1,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,12,
+                 0x00,0xFF,0x03,0x04,0x93,0xFA,0x96,0x7B,
+                 0x00,0xFF,0x2F,0x00];
+    let smf = SMFReader::read_smf_with_encoding(&mut &bytes[..],WINDOWS_31J).unwrap();
+    assert_eq!(smf.tracks[0].name,Some("日本".to_string()));
+
+    // latin1 is still the default
+    let smf = SMFReader::read_smf(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks[0].name,Some("\u{93}ú\u{96}{".to_string()));
+    assert_eq!(ReadOptions::default().encoding.name(),"iso-8859-1");
+}
+
+#[test]
+fn partial_read() {
+    let bytes = [0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0,3,0,96,
+                 0x4D,0x54,0x72,0x6B,0,0,0,8,
+                 0x00,0x90,0x3C,0x64,
+                 0x00,0xFF,0x2F,0x00,
+                 // an undefined status in the middle track
+                 0x4D,0x54,0x72,0x6B,0,0,0,8,
+                 0x00,0xF4,0x00,0x00,
+                 0x00,0xFF,0x2F,0x00,
+                 0x4D,0x54,0x72,0x6B,0,0,0,4,
+                 0x00,0xFF,0x2F,0x00];
+    assert!(SMFReader::read_smf(&mut &bytes[..]).is_err());
+
+    let (smf,errors,_) = SMFReader::read_smf_partial(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    assert_eq!(smf.tracks[0].events.len(),2);
+    assert_eq!(smf.tracks[1].events.len(),1);
+    assert_eq!(errors.len(),1);
+    match errors[0] {
+        (1,SMFError::MidiError(MidiError::InvalidStatus(0xF4))) => {}
+        (track,ref err) => panic!("unexpected error in track {}: {}",track,err),
+    }
+
+    // a track whose length runs past the end of the file
+    let mut truncated = bytes[..30].to_vec();
+    truncated[21] = 100;
+    let (smf,errors,_) = SMFReader::read_smf_partial(&mut &truncated[..]).unwrap();
+    assert!(smf.tracks.is_empty());
+    assert_eq!(errors.len(),1);
+    assert_eq!(errors[0].0,0);
+
+    let (smf,errors,_) = SMFReader::read_smf_partial(&mut &bytes[..30]).unwrap();
+    assert_eq!(smf.tracks.len(),1);
+    assert!(errors.is_empty());
+}
+
+#[test]
+fn resync_to_next_track() {
+    let track = [0x4D,0x54,0x72,0x6B,0,0,0,4,
+                 0x00,0xFF,0x2F,0x00];
+    // garbage with partial matches of the magic
+    let garbage = [0x4D,0x54,0x4D,0x54,0x72,0x00,0x4D];
+
+    let mut bytes = garbage.to_vec();
+    bytes.extend_from_slice(&track);
+    let mut reader = &bytes[..];
+    SMFReader::resync_to_next_track(&mut reader).unwrap();
+    assert_eq!(reader,&track[4..]);
+    assert!(SMFReader::resync_to_next_track(&mut reader).is_err());
+
+    let mut bytes = vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0,2,0,96];
+    bytes.extend_from_slice(&track);
+    bytes.extend_from_slice(&garbage);
+    bytes.extend_from_slice(&track);
+    assert!(SMFReader::read_smf(&mut &bytes[..]).is_err());
+    let (smf,errors,warnings) = SMFReader::read_smf_partial(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    assert!(errors.is_empty());
+    assert_eq!(warnings,vec![SMFWarning::SkippedBytes { track: 1, count: garbage.len() }]);
+
+    // the garbage doesn't take the index of a track that can't be read
+    let bad = [0x4D,0x54,0x72,0x6B,0,0,0,4,
+               0x00,0xF4,0x00,0x00];
+    let mut bytes = vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0,3,0,96];
+    bytes.extend_from_slice(&track);
+    bytes.extend_from_slice(&garbage);
+    bytes.extend_from_slice(&bad);
+    bytes.extend_from_slice(&track);
+    let (smf,errors,warnings) = SMFReader::read_smf_partial(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks.len(),2);
+    assert_eq!(errors.len(),1);
+    assert_eq!(errors[0].0,1);
+    assert_eq!(warnings,vec![SMFWarning::SkippedBytes { track: 1, count: garbage.len() }]);
+}
@@ -0,0 +1,231 @@
+use ::{Event,TrackEvent};
+
+/// The tempo of an SMF before any tempo setting event, in
+/// microseconds per quarter note (120 beats per minute)
+pub const DEFAULT_TEMPO: u32 = 500000;
+
+/// A TempoMap holds all the tempo changes of an SMF, and can be used
+/// to convert a time in ticks into real time.  Build one with
+/// `SMF::build_tempo_map`.
+#[derive(Debug,Clone)]
+pub struct TempoMap {
+    ticks_per_beat: u64,
+    // (absolute time in ticks, microseconds per quarter note), ordered by time
+    changes: Vec<(u64,u32)>,
+}
+
+impl TempoMap {
+    /// Build a tempo map from the tempo setting events in `events`.
+    /// `ticks_per_beat` must be positive.
+    pub fn from_events(events: &[TrackEvent], ticks_per_beat: u16) -> TempoMap {
+        let mut changes = Vec::new();
+        let mut time = 0;
+        for event in events.iter() {
+            time += event.vtime;
+            if let Event::Meta(ref meta) = event.event {
+                if let Some(tempo) = meta.tempo_micros_per_quarter() {
+                    changes.push((time,tempo));
+                }
+            }
+        }
+        TempoMap {
+            ticks_per_beat: ticks_per_beat as u64,
+            changes,
+        }
+    }
+
+    /// Convert an absolute time in ticks into microseconds since the
+    /// start of the track, taking into account every tempo change
+    /// before `tick`
+    pub fn ticks_to_micros(&self, tick: u64) -> u64 {
+        // sum up ticks * microseconds/beat for each section and only
+        // divide at the end, so rounding errors don't accumulate
+        let mut total = 0;
+        let mut last_time = 0;
+        let mut tempo = DEFAULT_TEMPO as u64;
+        for &(time, change) in self.changes.iter() {
+            if time >= tick {
+                break;
+            }
+            total += (time - last_time) * tempo;
+            last_time = time;
+            tempo = change as u64;
+        }
+        total += (tick - last_time) * tempo;
+        total / self.ticks_per_beat
+    }
+}
+
+/// A PositionMap holds all the time signature changes of an SMF, and
+/// can be used to convert a time in ticks into a musical position.
+/// Build one with `SMF::position_map`.
+#[derive(Debug,Clone)]
+pub struct PositionMap {
+    ticks_per_beat: u64,
+    // (absolute time in ticks, bar starting there, numerator,
+    // denominator exponent), ordered by time
+    changes: Vec<(u64,u32,u8,u8)>,
+}
+
+impl PositionMap {
+    /// Build a position map from the time signature events in
+    /// `events`.  The time signature is 4/4 until the first one.  A
+    /// time signature that doesn't fall on a bar line starts a new
+    /// bar.  `ticks_per_beat` must be positive.
+    pub fn from_events(events: &[TrackEvent], ticks_per_beat: u16) -> PositionMap {
+        let mut map = PositionMap {
+            ticks_per_beat: ticks_per_beat as u64,
+            changes: vec![(0,1,4,2)],
+        };
+        let mut time = 0;
+        for event in events.iter() {
+            time += event.vtime;
+            if let Event::Meta(ref meta) = event.event {
+                if let Some((numerator,exponent,_,_)) = meta.time_signature_data() {
+                    let (start,bar,num,exp) = *map.changes.last().unwrap();
+                    let bar_ticks = map.bar_ticks(num,exp);
+                    let bar = bar + (time - start).div_ceil(bar_ticks) as u32;
+                    if start == time {
+                        map.changes.pop();
+                    }
+                    map.changes.push((time,bar,numerator.max(1),exponent));
+                }
+            }
+        }
+        map
+    }
+
+    /// Build a position map from time signature changes given by
+    /// bar, as (bar, numerator, denominator exponent) ordered by bar.
+    /// Bars count from 1, and the time signature is 4/4 until the
+    /// first change.  `ticks_per_beat` must be positive.
+    pub fn from_bars(changes: &[(u32,u8,u8)], ticks_per_beat: u16) -> PositionMap {
+        let mut map = PositionMap {
+            ticks_per_beat: ticks_per_beat as u64,
+            changes: vec![(0,1,4,2)],
+        };
+        for &(bar,numerator,exponent) in changes.iter() {
+            let bar = bar.max(1);
+            let time = map.bbt_to_tick(bar,1);
+            if map.changes.last().map(|&(start,..)| start) == Some(time) {
+                map.changes.pop();
+            }
+            map.changes.push((time,bar,numerator.max(1),exponent));
+        }
+        map
+    }
+
+    fn beat_ticks(&self, exponent: u8) -> u64 {
+        ((self.ticks_per_beat * 4) >> exponent.min(63)).max(1)
+    }
+
+    fn bar_ticks(&self, numerator: u8, exponent: u8) -> u64 {
+        numerator as u64 * self.beat_ticks(exponent)
+    }
+
+    /// Convert an absolute time in ticks into a (bar, beat, tick)
+    /// position.  Bars and beats count from 1, as they do in a score,
+    /// and the tick is the offset into the beat.  Beats are in units
+    /// of the time signature's denominator, so there are three per
+    /// bar in 3/4 and six in 6/8.
+    pub fn tick_to_bbt(&self, tick: u64) -> (u32,u32,u32) {
+        let &(start,bar,numerator,exponent) = self.changes.iter().rev()
+            .find(|&&(time,..)| time <= tick)
+            .unwrap_or(&self.changes[0]);
+        let bar_ticks = self.bar_ticks(numerator,exponent);
+        let beat_ticks = self.beat_ticks(exponent);
+        let offset = tick - start;
+        let in_bar = offset % bar_ticks;
+        (bar + (offset / bar_ticks) as u32,
+         (in_bar / beat_ticks) as u32 + 1,
+         (in_bar % beat_ticks) as u32)
+    }
+
+    /// Convert a bar and beat into an absolute time in ticks, the
+    /// opposite of `tick_to_bbt`.  Bars and beats count from 1.
+    pub fn bbt_to_tick(&self, bar: u32, beat: u32) -> u64 {
+        let bar = bar.max(1);
+        let &(start,start_bar,numerator,exponent) = self.changes.iter().rev()
+            .find(|&&(_,b,..)| b <= bar)
+            .unwrap_or(&self.changes[0]);
+        start + (bar - start_bar) as u64 * self.bar_ticks(numerator,exponent)
+            + beat.saturating_sub(1) as u64 * self.beat_ticks(exponent)
+    }
+}
+
+#[test]
+fn two_tempos() {
+    use ::{SMFBuilder,MetaEvent,MidiMessage};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0,0,MetaEvent::tempo_setting(500000)).unwrap();
+    builder.add_meta_abs(0,960,MetaEvent::tempo_setting(250000)).unwrap();
+    builder.add_midi_abs(0,1440,MidiMessage::note_on(60,100,0)).unwrap();
+    let mut smf = builder.result();
+    smf.division = 480;
+
+    let map = smf.build_tempo_map().unwrap();
+    assert_eq!(map.ticks_to_micros(0),0);
+    assert_eq!(map.ticks_to_micros(480),500000);
+    assert_eq!(map.ticks_to_micros(960),1000000);
+    assert_eq!(map.ticks_to_micros(1440),1250000);
+
+    smf.division = -0x1828;
+    assert!(smf.build_tempo_map().is_none());
+}
+
+#[test]
+fn default_tempo() {
+    let map = TempoMap::from_events(&[],96);
+    assert_eq!(map.ticks_to_micros(96*4),2000000);
+}
+
+#[test]
+fn time_signature_changes() {
+    use ::{SMFBuilder,MetaEvent};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0,768,MetaEvent::time_signature(3,2,24,8)).unwrap();
+    builder.add_meta_abs(0,1344,MetaEvent::time_signature(6,3,24,8)).unwrap();
+    let mut smf = builder.result();
+    smf.division = 96;
+
+    let map = smf.position_map().unwrap();
+    assert_eq!(map.tick_to_bbt(0),(1,1,0));
+    assert_eq!(map.tick_to_bbt(384),(2,1,0));
+    assert_eq!(map.tick_to_bbt(767),(2,4,95));
+    // 3/4 from bar 3
+    assert_eq!(map.tick_to_bbt(768),(3,1,0));
+    assert_eq!(map.tick_to_bbt(1055),(3,3,95));
+    assert_eq!(map.tick_to_bbt(1056),(4,1,0));
+    // 6/8 from bar 5
+    assert_eq!(map.tick_to_bbt(1344),(5,1,0));
+    assert_eq!(map.tick_to_bbt(1344+48*5),(5,6,0));
+    assert_eq!(map.tick_to_bbt(1344+48*6),(6,1,0));
+
+    smf.division = -0x1828;
+    assert!(smf.position_map().is_none());
+}
+
+#[test]
+fn default_time_signature() {
+    let map = PositionMap::from_events(&[],480);
+    assert_eq!(map.tick_to_bbt(480*9+10),(3,2,10));
+    assert_eq!(map.bbt_to_tick(3,2),480*9);
+}
+
+#[test]
+fn time_signatures_by_bar() {
+    // 3/4 from bar 3 and 6/8 from bar 5, as in time_signature_changes
+    let map = PositionMap::from_bars(&[(3,3,2),(5,6,3)],96);
+    assert_eq!(map.bbt_to_tick(1,1),0);
+    assert_eq!(map.bbt_to_tick(3,1),768);
+    assert_eq!(map.bbt_to_tick(4,1),1056);
+    assert_eq!(map.bbt_to_tick(5,6),1344+48*5);
+    for &tick in [0,767,768,1055,1344,1344+48*6+7].iter() {
+        let (bar,beat,offset) = map.tick_to_bbt(tick);
+        assert_eq!(map.bbt_to_tick(bar,beat) + offset as u64,tick);
+    }
+}
@@ -1,11 +1,22 @@
 //! Some useful utility functions
 
-use std::iter;
+#[cfg(feature = "std")]
 use std::io::{Read,Error,ErrorKind};
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
 
+#[cfg(feature = "std")]
+use encoding::EncodingRef;
+
+use meta::Mode;
+
+#[cfg(feature = "std")]
 static NSTRS: &'static str = "C C#D D#E F F#G G#A A#B ";
 
 /// convert a midi note number to a name
+#[cfg(feature = "std")]
 pub fn note_num_to_name(num: u32) -> String {
     let oct = (num as f32 /12 as f32).floor()-1.0;
     let nmt = ((num%12)*2) as usize;
@@ -18,59 +29,422 @@ pub fn note_num_to_name(num: u32) -> String {
     format!("{}{}",slice,oct)
 }
 
-/// Read a single byte from a Reader
+/// convert a note name like "C3", "C#3", "Db3" or "A-1" to a midi
+/// note number, using the same octave numbering as
+/// `note_num_to_name`.  Returns None if the name can't be parsed or
+/// the note is outside 0..127
+pub fn name_to_note_num(name: &str) -> Option<u8> {
+    let mut chars = name.chars();
+    let base = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let mut rest = chars.as_str();
+    let accidental = if rest.starts_with('#') {
+        rest = &rest[1..];
+        1
+    } else if rest.starts_with('b') {
+        rest = &rest[1..];
+        -1
+    } else {
+        0
+    };
+    let octave: i32 = rest.parse().ok()?;
+    let num = (octave + 1) * 12 + base + accidental;
+    if (0..=127).contains(&num) {
+        Some(num as u8)
+    } else {
+        None
+    }
+}
+
+/// convert a midi note number to its frequency in Hz, using equal
+/// temperament with A4 (note 69) at 440 Hz
+#[cfg(feature = "std")]
+pub fn note_num_to_freq(num: u8) -> f64 {
+    note_num_to_freq_with_tuning(num, 440.0)
+}
+
+/// convert a midi note number to its frequency in Hz, using equal
+/// temperament with A4 (note 69) at `a4_hz`
+#[cfg(feature = "std")]
+pub fn note_num_to_freq_with_tuning(num: u8, a4_hz: f64) -> f64 {
+    a4_hz * 2f64.powf((num as f64 - 69.0) / 12.0)
+}
+
+static MAJOR_KEYS: [&str; 15] = [
+    "Cb major", "Gb major", "Db major", "Ab major", "Eb major", "Bb major", "F major", "C major",
+    "G major", "D major", "A major", "E major", "B major", "F# major", "C# major",
+];
+
+static MINOR_KEYS: [&str; 15] = [
+    "Ab minor", "Eb minor", "Bb minor", "F minor", "C minor", "G minor", "D minor", "A minor",
+    "E minor", "B minor", "F# minor", "C# minor", "G# minor", "D# minor", "A# minor",
+];
+
+/// Get the name of the key with `sharps_flats` sharps (positive) or
+/// flats (negative) in the given mode, e.g. -3 and Major gives "Eb
+/// major".  Returns None if there are more than 7 sharps or flats
+pub fn key_name(sharps_flats: i8, mode: Mode) -> Option<&'static str> {
+    if !(-7..=7).contains(&sharps_flats) {
+        return None;
+    }
+    let index = (sharps_flats + 7) as usize;
+    Some(match mode {
+        Mode::Major => MAJOR_KEYS[index],
+        Mode::Minor => MINOR_KEYS[index],
+    })
+}
+
+/// Get the name of a controller number as defined by the MIDI
+/// specification, e.g. 1 gives "Modulation" and 64 "Sustain Pedal".
+/// Returns None for undefined controllers
+pub fn controller_name(controller: u8) -> Option<&'static str> {
+    Some(match controller {
+        0 => "Bank Select",
+        1 => "Modulation",
+        2 => "Breath Controller",
+        4 => "Foot Controller",
+        5 => "Portamento Time",
+        6 => "Data Entry",
+        7 => "Channel Volume",
+        8 => "Balance",
+        10 => "Pan",
+        11 => "Expression",
+        12 => "Effect Control 1",
+        13 => "Effect Control 2",
+        16 => "General Purpose Controller 1",
+        17 => "General Purpose Controller 2",
+        18 => "General Purpose Controller 3",
+        19 => "General Purpose Controller 4",
+        32 => "Bank Select LSB",
+        33 => "Modulation LSB",
+        34 => "Breath Controller LSB",
+        36 => "Foot Controller LSB",
+        37 => "Portamento Time LSB",
+        38 => "Data Entry LSB",
+        39 => "Channel Volume LSB",
+        40 => "Balance LSB",
+        42 => "Pan LSB",
+        43 => "Expression LSB",
+        44 => "Effect Control 1 LSB",
+        45 => "Effect Control 2 LSB",
+        48 => "General Purpose Controller 1 LSB",
+        49 => "General Purpose Controller 2 LSB",
+        50 => "General Purpose Controller 3 LSB",
+        51 => "General Purpose Controller 4 LSB",
+        64 => "Sustain Pedal",
+        65 => "Portamento On/Off",
+        66 => "Sostenuto",
+        67 => "Soft Pedal",
+        68 => "Legato Footswitch",
+        69 => "Hold 2",
+        70 => "Sound Variation",
+        71 => "Timbre/Harmonic Intensity",
+        72 => "Release Time",
+        73 => "Attack Time",
+        74 => "Brightness",
+        75 => "Decay Time",
+        76 => "Vibrato Rate",
+        77 => "Vibrato Depth",
+        78 => "Vibrato Delay",
+        79 => "Sound Controller 10",
+        80 => "General Purpose Controller 5",
+        81 => "General Purpose Controller 6",
+        82 => "General Purpose Controller 7",
+        83 => "General Purpose Controller 8",
+        84 => "Portamento Control",
+        88 => "High Resolution Velocity Prefix",
+        91 => "Reverb Depth",
+        92 => "Tremolo Depth",
+        93 => "Chorus Depth",
+        94 => "Detune Depth",
+        95 => "Phaser Depth",
+        96 => "Data Increment",
+        97 => "Data Decrement",
+        98 => "NRPN LSB",
+        99 => "NRPN MSB",
+        100 => "RPN LSB",
+        101 => "RPN MSB",
+        120 => "All Sound Off",
+        121 => "Reset All Controllers",
+        122 => "Local Control",
+        123 => "All Notes Off",
+        124 => "Omni Mode Off",
+        125 => "Omni Mode On",
+        126 => "Mono Mode On",
+        127 => "Poly Mode On",
+        _ => return None,
+    })
+}
+
+/// Get the controller number for a name, ignoring case.  Accepts the
+/// names given by `controller_name` as well as the short names
+/// "volume" and "sustain", so "pan" gives 10 and "Sustain" gives 64.
+pub fn controller_number(name: &str) -> Option<u8> {
+    match name.to_ascii_lowercase().as_str() {
+        "volume" => return Some(7),
+        "sustain" => return Some(64),
+        _ => {}
+    }
+    (0..128).find(|&cc| controller_name(cc).is_some_and(|cc_name| cc_name.eq_ignore_ascii_case(name)))
+}
+
+static GM_PROGRAMS: [&str; 128] = [
+    "Acoustic Grand Piano", "Bright Acoustic Piano", "Electric Grand Piano", "Honky-tonk Piano",
+    "Electric Piano 1", "Electric Piano 2", "Harpsichord", "Clavi", "Celesta", "Glockenspiel",
+    "Music Box", "Vibraphone", "Marimba", "Xylophone", "Tubular Bells", "Dulcimer", "Drawbar Organ",
+    "Percussive Organ", "Rock Organ", "Church Organ", "Reed Organ", "Accordion", "Harmonica",
+    "Tango Accordion", "Acoustic Guitar (nylon)", "Acoustic Guitar (steel)",
+    "Electric Guitar (jazz)", "Electric Guitar (clean)", "Electric Guitar (muted)",
+    "Overdriven Guitar", "Distortion Guitar", "Guitar harmonics", "Acoustic Bass",
+    "Electric Bass (finger)", "Electric Bass (pick)", "Fretless Bass", "Slap Bass 1", "Slap Bass 2",
+    "Synth Bass 1", "Synth Bass 2", "Violin", "Viola", "Cello", "Contrabass", "Tremolo Strings",
+    "Pizzicato Strings", "Orchestral Harp", "Timpani", "String Ensemble 1", "String Ensemble 2",
+    "SynthStrings 1", "SynthStrings 2", "Choir Aahs", "Voice Oohs", "Synth Voice", "Orchestra Hit",
+    "Trumpet", "Trombone", "Tuba", "Muted Trumpet", "French Horn", "Brass Section", "SynthBrass 1",
+    "SynthBrass 2", "Soprano Sax", "Alto Sax", "Tenor Sax", "Baritone Sax", "Oboe", "English Horn",
+    "Bassoon", "Clarinet", "Piccolo", "Flute", "Recorder", "Pan Flute", "Blown Bottle",
+    "Shakuhachi", "Whistle", "Ocarina", "Lead 1 (square)", "Lead 2 (sawtooth)", "Lead 3 (calliope)",
+    "Lead 4 (chiff)", "Lead 5 (charang)", "Lead 6 (voice)", "Lead 7 (fifths)",
+    "Lead 8 (bass + lead)", "Pad 1 (new age)", "Pad 2 (warm)", "Pad 3 (polysynth)", "Pad 4 (choir)",
+    "Pad 5 (bowed)", "Pad 6 (metallic)", "Pad 7 (halo)", "Pad 8 (sweep)", "FX 1 (rain)",
+    "FX 2 (soundtrack)", "FX 3 (crystal)", "FX 4 (atmosphere)", "FX 5 (brightness)",
+    "FX 6 (goblins)", "FX 7 (echoes)", "FX 8 (sci-fi)", "Sitar", "Banjo", "Shamisen", "Koto",
+    "Kalimba", "Bag pipe", "Fiddle", "Shanai", "Tinkle Bell", "Agogo", "Steel Drums", "Woodblock",
+    "Taiko Drum", "Melodic Tom", "Synth Drum", "Reverse Cymbal", "Guitar Fret Noise",
+    "Breath Noise", "Seashore", "Bird Tweet", "Telephone Ring", "Helicopter", "Applause", "Gunshot",
+];
+
+static GM_DRUMS: [&str; 47] = [
+    "Acoustic Bass Drum", "Bass Drum 1", "Side Stick", "Acoustic Snare", "Hand Clap",
+    "Electric Snare", "Low Floor Tom", "Closed Hi Hat", "High Floor Tom", "Pedal Hi-Hat", "Low Tom",
+    "Open Hi-Hat", "Low-Mid Tom", "Hi-Mid Tom", "Crash Cymbal 1", "High Tom", "Ride Cymbal 1",
+    "Chinese Cymbal", "Ride Bell", "Tambourine", "Splash Cymbal", "Cowbell", "Crash Cymbal 2",
+    "Vibraslap", "Ride Cymbal 2", "Hi Bongo", "Low Bongo", "Mute Hi Conga", "Open Hi Conga",
+    "Low Conga", "High Timbale", "Low Timbale", "High Agogo", "Low Agogo", "Cabasa", "Maracas",
+    "Short Whistle", "Long Whistle", "Short Guiro", "Long Guiro", "Claves", "Hi Wood Block",
+    "Low Wood Block", "Mute Cuica", "Open Cuica", "Mute Triangle", "Open Triangle",
+];
+
+/// Get the General MIDI instrument name of a program number, e.g. 0
+/// gives "Acoustic Grand Piano".  Returns "Unknown" for numbers over
+/// 127
+pub fn gm_program_name(program: u8) -> &'static str {
+    match GM_PROGRAMS.get(program as usize) {
+        Some(name) => name,
+        None => "Unknown",
+    }
+}
+
+/// Get the name of the General MIDI percussion sound of a note on
+/// channel 10 (channel 9 counting from 0), e.g. 38 gives "Acoustic
+/// Snare".  Returns None for notes outside 35..81
+pub fn gm_drum_name(note: u8) -> Option<&'static str> {
+    if note < 35 {
+        return None;
+    }
+    GM_DRUMS.get((note - 35) as usize).cloned()
+}
+
+/// Combine two seven bit data bytes into a fourteen bit value, as
+/// used by pitch bend, song position and paired controllers.  The top
+/// bit of each byte is ignored.
+pub fn combine_7bit(lsb: u8, msb: u8) -> u16 {
+    ((msb & 0x7F) as u16) << 7 | (lsb & 0x7F) as u16
+}
+
+/// Split the low fourteen bits of `val` into two seven bit data
+/// bytes, returned as (lsb, msb)
+pub fn split_14bit(val: u16) -> (u8,u8) {
+    ((val & 0x7F) as u8, ((val >> 7) & 0x7F) as u8)
+}
+
+/// A small xorshift random number generator, used where a seeded,
+/// repeatable sequence is all that's needed
+#[cfg(feature = "std")]
+pub struct XorShift {
+    state: u64,
+}
+
+#[cfg(feature = "std")]
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // the state must never be zero, or it would stay zero forever
+        XorShift { state: if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed } }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    /// A value in `-spread..=spread`
+    pub fn spread(&mut self, spread: u64) -> i64 {
+        if spread == 0 {
+            return 0;
+        }
+        (self.next_u64() % (2 * spread + 1)) as i64 - spread as i64
+    }
+}
+
+/// Encode `val` as a variable length quantity, as used for delta
+/// times and lengths in SMFs: seven bits per byte, most significant
+/// first, with the top bit set on every byte but the last
+pub fn encode_vlq(val: u64) -> Vec<u8> {
+    let mut storage = Vec::new();
+    let mut cur = val;
+    let mut continuation = false;
+    let cont_mask = 0x80u8;
+    let val_mask = 0x7Fu64;
+    loop {
+        let mut to_write = (cur & val_mask) as u8;
+        cur >>= 7;
+        if continuation {
+            // we're writing a continuation byte, so set the bit
+            to_write |= cont_mask;
+        }
+        storage.push(to_write);
+        continuation = true;
+        if cur == 0 { break; }
+    }
+    storage.reverse();
+    storage
+}
+
+// The longest variable length quantity that's accepted
+const MAX_VLQ_LEN: usize = 9;
+
+/// Decode the variable length quantity (see `encode_vlq`) at the
+/// start of `bytes`.  Returns the value and the number of bytes it
+/// took up, or None if `bytes` ends first or the value is longer
+/// than 9 bytes
+pub fn decode_vlq_slice(bytes: &[u8]) -> Option<(u64,usize)> {
+    let mut res: u64 = 0;
+    for (i,&next) in bytes.iter().take(MAX_VLQ_LEN).enumerate() {
+        res = (res << 7) | (next & 0x7F) as u64;
+        if (next & 0x80) == 0 {
+            return Some((res,i + 1));
+        }
+    }
+    None
+}
+
+/// Read a variable length quantity (see `encode_vlq`) from a Reader.
+/// Values longer than 9 bytes are rejected as invalid data
+#[cfg(feature = "std")]
+pub fn decode_vlq(reader: &mut dyn Read) -> Result<u64,Error> {
+    let mut buf = [0; MAX_VLQ_LEN];
+    for len in 1..=MAX_VLQ_LEN {
+        buf[len - 1] = read_byte(reader)?;
+        if let Some((res,_)) = decode_vlq_slice(&buf[..len]) {
+            return Ok(res);
+        }
+    }
+    Err(Error::new(ErrorKind::InvalidData, "Variable length value too long"))
+}
+
+/// Read a single byte from a Reader.  Returns an error if the
+/// reader is at its end
+#[cfg(feature = "std")]
 pub fn read_byte(reader: &mut dyn Read) -> Result<u8,Error> {
     let mut b = [0; 1];
-    reader.read(&mut b)?;
+    fill_buf(reader,&mut b)?;
     Ok(b[0])
 }
 
-/// Read from reader until buffer is full, or an error occurs
-pub fn fill_buf(reader: &mut dyn Read, buf: &mut [u8]) -> Result<(),Error> {
+/// Read from reader until buffer is full or the reader is at its
+/// end, returning the number of bytes read.  Reads that are
+/// interrupted are retried, as are reads that would block (e.g. from
+/// a non-blocking socket), after yielding the thread.  Note that this
+/// busy-waits on a reader that would block, using a whole core until
+/// data arrives.
+#[cfg(feature = "std")]
+pub fn read_up_to(reader: &mut dyn Read, buf: &mut [u8]) -> Result<usize,Error> {
     let mut read = 0;
     while read < buf.len() {
-        let bytes_read = reader.read(&mut buf[read..])?;
-        if bytes_read == 0 {
-            return Err(Error::new(ErrorKind::InvalidData, "file ends before it should"));
+        match reader.read(&mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => thread::yield_now(),
+            Err(e) => return Err(e),
         }
-        read += bytes_read;
+    }
+    Ok(read)
+}
+
+/// Read from reader until buffer is full, or an error occurs.  Reads
+/// are retried like in `read_up_to`, so this also busy-waits on a
+/// reader that would block.
+#[cfg(feature = "std")]
+pub fn fill_buf(reader: &mut dyn Read, buf: &mut [u8]) -> Result<(),Error> {
+    if read_up_to(reader,buf)? < buf.len() {
+        return Err(Error::new(ErrorKind::InvalidData, "file ends before it should"));
     }
     Ok(())
 }
 
+/// Read and throw away up to `amt` bytes from reader, returning the
+/// number of bytes skipped, which is less than `amt` if the reader
+/// ends first.  Reads are retried like in `read_up_to`.
+#[cfg(feature = "std")]
+pub fn skip_amount(reader: &mut dyn Read, amt: u64) -> Result<u64,Error> {
+    let mut chunk = [0; 4096];
+    let mut left = amt;
+    while left > 0 {
+        let want = left.min(chunk.len() as u64) as usize;
+        let read = read_up_to(reader,&mut chunk[..want])?;
+        left -= read as u64;
+        if read < want {
+            break;
+        }
+    }
+    Ok(amt - left)
+}
+
+/// Read everything that's left in reader onto the end of dest.
+/// Reads are retried like in `read_up_to`.
+#[cfg(feature = "std")]
+pub fn read_to_end(reader: &mut dyn Read, dest: &mut Vec<u8>) -> Result<(),Error> {
+    let mut chunk = [0; 4096];
+    loop {
+        let read = read_up_to(reader,&mut chunk)?;
+        dest.extend_from_slice(&chunk[..read]);
+        if read < chunk.len() {
+            return Ok(());
+        }
+    }
+}
+
 /// Read amt from reader and put result in dest.  Errors in underlying
-/// reader will cause this function to return an error
+/// reader will cause this function to return an error.  Reads that
+/// are interrupted are retried
+#[cfg(feature = "std")]
 pub fn read_amount(reader: &mut dyn Read, dest: &mut Vec<u8>, amt: usize) -> Result<(),Error> {
     let start_len = dest.len();
-    let mut len = start_len;
-    if dest.capacity() < start_len + amt {
-        dest.extend(iter::repeat(0).take(amt));
-    }
-    let mut ret = Ok(());
-    while (len-start_len) < amt {
-        match reader.read(&mut dest[len..]) {
-            Ok(0) => {
-                // read 0 before amount
-                ret = Err(Error::new(ErrorKind::InvalidData,
-                                     "Stream ended before specified number of bytes could be read"));
-            },
-            Ok(n) => len += n,
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => {}
-            Err(e) => {
-                ret = Err(e);
-                break;
-            }
-        }
+    dest.resize(start_len + amt, 0);
+    let ret = fill_buf(reader, &mut dest[start_len..]);
+    if ret.is_err() {
+        dest.truncate(start_len);
     }
-    dest.truncate(len);
     ret
 }
 
-pub fn latin1_decode(s: &[u8]) -> String {
-    use encoding::{Encoding, DecoderTrap};
-    use encoding::all::ISO_8859_1;
+// Decode text in `encoding`, replacing anything that can't be decoded
+#[cfg(feature = "std")]
+pub fn decode_text(s: &[u8], encoding: EncodingRef) -> String {
+    use encoding::DecoderTrap;
     use std::str;
-    match ISO_8859_1.decode(s, DecoderTrap::Replace) {
+    match encoding.decode(s, DecoderTrap::Replace) {
         Ok(s) => s,
         Err(_) => match str::from_utf8(s) {
             Ok(s) => s.to_string(),
@@ -79,6 +453,7 @@ pub fn latin1_decode(s: &[u8]) -> String {
     }
 }
 
+#[cfg(feature = "std")]
 #[test]
 fn test_note_num_to_name() {
     assert_eq!(&note_num_to_name(48)[..],"C3");
@@ -86,3 +461,165 @@ fn test_note_num_to_name() {
     assert_eq!(&note_num_to_name(65)[..],"F4");
     assert_eq!(&note_num_to_name(104)[..],"G#7");
 }
+
+#[cfg(feature = "std")]
+#[test]
+fn test_note_num_to_freq() {
+    assert_eq!(note_num_to_freq(69),440.0);
+    assert!((note_num_to_freq(57) - 220.0).abs() < 1e-9);
+    assert!((note_num_to_freq(0) - 8.1758).abs() < 1e-4);
+    assert!((note_num_to_freq(127) - 12543.8540).abs() < 1e-4);
+    assert_eq!(note_num_to_freq_with_tuning(69,432.0),432.0);
+    assert!((note_num_to_freq_with_tuning(81,415.0) - 830.0).abs() < 1e-9);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_name_to_note_num() {
+    assert_eq!(name_to_note_num("C3"),Some(48));
+    assert_eq!(name_to_note_num("C#3"),Some(49));
+    assert_eq!(name_to_note_num("Db3"),Some(49));
+    assert_eq!(name_to_note_num("a4"),Some(69));
+    assert_eq!(name_to_note_num("C-1"),Some(0));
+    assert_eq!(name_to_note_num("G9"),Some(127));
+    assert_eq!(name_to_note_num("H2"),None);
+    assert_eq!(name_to_note_num("Cb-1"),None);
+    assert_eq!(name_to_note_num("G#9"),None);
+    assert_eq!(name_to_note_num("C"),None);
+    for num in 0..128 {
+        assert_eq!(name_to_note_num(&note_num_to_name(num)),Some(num as u8));
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_vlq() {
+    assert_eq!(encode_vlq(0),vec![0x00]);
+    assert_eq!(encode_vlq(127),vec![0x7F]);
+    assert_eq!(encode_vlq(255),vec![0x81,0x7F]);
+    assert_eq!(encode_vlq(32768),vec![0x82,0x80,0x00]);
+
+    for val in [0,127,128,255,32768,0x0FFFFFFF,1 << 62].iter() {
+        assert_eq!(decode_vlq(&mut &encode_vlq(*val)[..]).unwrap(),*val);
+    }
+    assert!(decode_vlq(&mut &[0x80;10][..]).is_err());
+}
+
+#[test]
+fn test_vlq_slice() {
+    assert_eq!(decode_vlq_slice(&[0x82,0x80,0x00,0x05]),Some((32768,3)));
+    assert_eq!(decode_vlq_slice(&[0x82,0x80]),None);
+    assert_eq!(decode_vlq_slice(&[0x80;10]),None);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_read_byte() {
+    // returns at most one byte per read
+    struct OneByte<'a>(&'a [u8]);
+    impl<'a> Read for OneByte<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize,Error> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    let mut reader = OneByte(&[1,2]);
+    assert_eq!(read_byte(&mut reader).unwrap(),1);
+    assert_eq!(read_byte(&mut reader).unwrap(),2);
+    assert!(read_byte(&mut reader).is_err());
+    assert!(read_byte(&mut &[][..]).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_interrupted_reads() {
+    // fails with Interrupted before every successful read
+    struct Interrupting<'a> { data: &'a [u8], interrupt: bool }
+    impl<'a> Read for Interrupting<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize,Error> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(Error::new(ErrorKind::Interrupted, "interrupted"));
+            }
+            let len = buf.len().min(2);
+            self.data.read(&mut buf[..len])
+        }
+    }
+
+    let mut reader = Interrupting { data: &[1,2,3,4,5,6,7], interrupt: false };
+    let mut buf = [0;3];
+    fill_buf(&mut reader,&mut buf).unwrap();
+    assert_eq!(buf,[1,2,3]);
+    let mut dest = Vec::with_capacity(16);
+    read_amount(&mut reader,&mut dest,3).unwrap();
+    assert_eq!(dest,vec![4,5,6]);
+    assert_eq!(read_byte(&mut reader).unwrap(),7);
+
+    // running out of data is still an error
+    assert!(read_amount(&mut reader,&mut dest,2).is_err());
+    assert_eq!(dest,vec![4,5,6]);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_would_block_reads() {
+    // like a non-blocking socket that has no data ready the first time
+    struct Blocking<'a> { data: &'a [u8], blocked: bool }
+    impl<'a> Read for Blocking<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize,Error> {
+            if !self.blocked {
+                self.blocked = true;
+                return Err(Error::new(ErrorKind::WouldBlock, "would block"));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    let mut reader = Blocking { data: &[0x81,0x00,9], blocked: false };
+    assert_eq!(decode_vlq(&mut reader).unwrap(),0x80);
+    assert_eq!(read_byte(&mut reader).unwrap(),9);
+}
+
+#[test]
+fn test_controller_name() {
+    assert_eq!(controller_name(1),Some("Modulation"));
+    assert_eq!(controller_name(33),Some("Modulation LSB"));
+    assert_eq!(controller_name(64),Some("Sustain Pedal"));
+    assert_eq!(controller_name(3),None);
+    assert_eq!(controller_name(35),None);
+    assert_eq!(controller_name(200),None);
+
+    assert_eq!(controller_number("pan"),Some(10));
+    assert_eq!(controller_number("Modulation LSB"),Some(33));
+    assert_eq!(controller_number("VOLUME"),Some(7));
+    assert_eq!(controller_number("sustain"),Some(64));
+    assert_eq!(controller_number("kazoo"),None);
+}
+
+#[test]
+fn test_gm_names() {
+    assert_eq!(gm_program_name(0),"Acoustic Grand Piano");
+    assert_eq!(gm_program_name(40),"Violin");
+    assert_eq!(gm_program_name(127),"Gunshot");
+    assert_eq!(gm_program_name(128),"Unknown");
+    assert_eq!(gm_drum_name(35),Some("Acoustic Bass Drum"));
+    assert_eq!(gm_drum_name(38),Some("Acoustic Snare"));
+    assert_eq!(gm_drum_name(81),Some("Open Triangle"));
+    assert_eq!(gm_drum_name(34),None);
+    assert_eq!(gm_drum_name(82),None);
+}
+
+#[test]
+fn test_14bit() {
+    assert_eq!(split_14bit(0x2000),(0x00,0x40));
+    assert_eq!(combine_7bit(0x00,0x40),0x2000);
+    assert_eq!(split_14bit(0x3FFF),(0x7F,0x7F));
+    assert_eq!(combine_7bit(0x7F,0x7F),0x3FFF);
+    for &val in [0,1,0x7F,0x80,0x2000,0x3FFF].iter() {
+        let (lsb,msb) = split_14bit(val);
+        assert_eq!(combine_7bit(lsb,msb),val);
+    }
+    // bits above fourteen are dropped
+    assert_eq!(split_14bit(0xFFFF),(0x7F,0x7F));
+}
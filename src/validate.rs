@@ -0,0 +1,132 @@
+use std::collections::HashMap;
+
+use ::{Event,MetaCommand,SMF,SMFFormat,Status};
+
+/// A problem found by `SMF::validate`.  Times are absolute times in
+/// ticks from the start of the track.
+#[derive(Debug,Clone,PartialEq)]
+pub enum ValidationWarning {
+    /// Track `track` doesn't end with an EndOfTrack event
+    MissingEndOfTrack { track: usize },
+    /// A NoteOn in track `track` is never followed by a matching
+    /// NoteOff (or NoteOn with velocity 0)
+    DanglingNote { track: usize, time: u64, channel: u8, note: u8 },
+    /// A MIDIChannelPrefixAssignment or MIDIPortPrefixAssignment
+    /// event doesn't have a single data byte in range
+    MalformedPrefix { track: usize, time: u64, command: MetaCommand },
+    /// A tempo setting event is in a track other than the first one
+    /// of a multi track file
+    TempoOutsideFirstTrack { track: usize, time: u64 },
+}
+
+pub fn validate(smf: &SMF) -> Vec<ValidationWarning> {
+    let mut warnings = Vec::new();
+    for (track_num,track) in smf.tracks.iter().enumerate() {
+        // start times of the notes that are currently on, by channel and note
+        let mut on: HashMap<(u8,u8),Vec<u64>> = HashMap::new();
+        let mut time = 0;
+        for event in track.events.iter() {
+            time += event.vtime;
+            match event.event {
+                Event::Midi(ref msg) => {
+                    let key = match (msg.channel(),msg.note()) {
+                        (Some(channel),Some(note)) => (channel,note),
+                        _ => continue,
+                    };
+                    match msg.effective_status() {
+                        Status::NoteOn => {
+                            on.entry(key).or_default().push(time);
+                        }
+                        Status::NoteOff => {
+                            if let Some(starts) = on.get_mut(&key) {
+                                if !starts.is_empty() {
+                                    starts.remove(0);
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                Event::Meta(ref meta) => {
+                    match meta.command {
+                        MetaCommand::MIDIChannelPrefixAssignment |
+                        MetaCommand::MIDIPortPrefixAssignment => {
+                            let max = if meta.command == MetaCommand::MIDIChannelPrefixAssignment { 15 } else { 127 };
+                            if meta.data.len() != 1 || meta.data[0] > max {
+                                warnings.push(ValidationWarning::MalformedPrefix {
+                                    track: track_num,
+                                    time,
+                                    command: meta.command,
+                                });
+                            }
+                        }
+                        MetaCommand::TempoSetting if track_num > 0 && smf.format == SMFFormat::MultiTrack => {
+                            warnings.push(ValidationWarning::TempoOutsideFirstTrack { track: track_num, time });
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        let mut dangling: Vec<(u64,u8,u8)> = on.into_iter().flat_map(|((channel,note),starts)| {
+            starts.into_iter().map(move |start| (start,channel,note))
+        }).collect();
+        dangling.sort();
+        for (start,channel,note) in dangling {
+            warnings.push(ValidationWarning::DanglingNote { track: track_num, time: start, channel, note });
+        }
+
+        let ends_with_eot = match track.events.last() {
+            Some(event) => match event.event {
+                Event::Meta(ref meta) => meta.command == MetaCommand::EndOfTrack,
+                Event::Midi(_) => false,
+            },
+            None => false,
+        };
+        if !ends_with_eot {
+            warnings.push(ValidationWarning::MissingEndOfTrack { track: track_num });
+        }
+    }
+    warnings
+}
+
+#[test]
+fn dangling_note() {
+    use ::{MetaEvent,MidiMessage,SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0,0,MetaEvent::end_of_track()).unwrap();
+    builder.add_track();
+    builder.add_midi_abs(1,0,MidiMessage::note_on(60,100,0)).unwrap();
+    builder.add_midi_abs(1,0,MidiMessage::note_on(64,100,0)).unwrap();
+    builder.add_midi_abs(1,10,MidiMessage::note_on(60,0,0)).unwrap();
+    builder.add_midi_abs(1,20,MidiMessage::note_on(67,100,1)).unwrap();
+    builder.add_midi_abs(1,30,MidiMessage::note_off(67,0,1)).unwrap();
+    builder.add_meta_abs(1,30,MetaEvent::tempo_setting(400000)).unwrap();
+    builder.add_meta_abs(1,40,MetaEvent::end_of_track()).unwrap();
+    let smf = builder.result();
+
+    assert_eq!(validate(&smf),vec![
+        ValidationWarning::TempoOutsideFirstTrack { track: 1, time: 30 },
+        ValidationWarning::DanglingNote { track: 1, time: 0, channel: 0, note: 64 },
+    ]);
+    assert_eq!(smf.validate(),validate(&smf));
+}
+
+#[test]
+fn malformed_events() {
+    use ::{MetaEvent,SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_meta_abs(0,0,MetaEvent::midichannel_prefix_assignment(16)).unwrap();
+    builder.add_meta_abs(0,0,MetaEvent::midiport_prefix_assignment(1)).unwrap();
+    let smf = builder.result();
+
+    assert_eq!(validate(&smf),vec![
+        ValidationWarning::MalformedPrefix { track: 0, time: 0, command: MetaCommand::MIDIChannelPrefixAssignment },
+        ValidationWarning::MissingEndOfTrack { track: 0 },
+    ]);
+}
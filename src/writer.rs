@@ -1,11 +1,17 @@
 use std::fs::OpenOptions;
-use std::io::{Error,Write};
+use std::io::{Error,ErrorKind,Write};
 use std::path::Path;
 
 use byteorder::{BigEndian, WriteBytesExt};
 
 use SMF;
-use ::{Event,AbsoluteEvent,MetaEvent,MetaCommand,SMFFormat};
+use ::{Division,Event,AbsoluteEvent,MetaEvent,MetaCommand,SMFFormat,Track,TrackEvent};
+
+use util::encode_vlq;
+
+/// The largest value that can be written as a delta time or length
+/// in an SMF (28 bits)
+pub const MAX_VTIME: u64 = 0x0FFFFFFF;
 
 /// An SMFWriter is used to write an SMF to a file.  It can be either
 /// constructed empty and have tracks added, or created from an
@@ -25,7 +31,13 @@ use ::{Event,AbsoluteEvent,MetaEvent,MetaCommand,SMFFormat};
 pub struct SMFWriter {
     format: u16,
     ticks: i16,
-    tracks: Vec<Vec<u8>>,
+    // the events of each track, kept so they can be encoded again when
+    // the encoding options change
+    tracks: Vec<Vec<TrackEvent>>,
+    // the encoded bytes of each track, or None if it can't be encoded
+    encoded: Vec<Option<Vec<u8>>>,
+    running_status: bool,
+    auto_end_of_track: bool,
 }
 
 impl SMFWriter {
@@ -37,9 +49,18 @@ impl SMFWriter {
             format: 1,
             ticks: ticks,
             tracks: Vec::new(),
+            encoded: Vec::new(),
+            running_status: false,
+            auto_end_of_track: true,
         }
     }
 
+    /// Create a new SMFWriter with the given division.  The SMFWriter
+    /// will initially have no tracks.
+    pub fn from_division(division: Division) -> SMFWriter {
+        SMFWriter::new_with_division(division.to_i16())
+    }
+
     /// Create a new SMFWriter with the given format and number of
     /// units per beat.  The SMFWriter will initially have no tracks.
     pub fn new_with_division_and_format(format: SMFFormat, ticks: i16) -> SMFWriter {
@@ -47,55 +68,90 @@ impl SMFWriter {
             format: format as u16,
             ticks: ticks,
             tracks: Vec::new(),
+            encoded: Vec::new(),
+            running_status: false,
+            auto_end_of_track: true,
         }
     }
 
     /// Create a writer that has all the tracks from the given SMF already added
+    ///
+    /// The tracks aren't checked until they're written, so a delta
+    /// time or length over `MAX_VTIME` makes the write fail.  Use
+    /// `try_from_smf` to check them up front.
     pub fn from_smf(smf: SMF) -> SMFWriter {
         let mut writer = SMFWriter::new_with_division_and_format
             (smf.format, smf.division);
 
+        writer.tracks = smf.tracks.into_iter().map(|track| track.events).collect();
+        writer.encode_tracks();
+        writer
+    }
+
+    /// Create a writer that has all the tracks from the given SMF
+    /// already added, returning an error if one of them can't be
+    /// written (see `add_smf_track`)
+    pub fn try_from_smf(smf: &SMF) -> Result<SMFWriter,Error> {
+        let mut writer = SMFWriter::new_with_division_and_format
+            (smf.format, smf.division);
+
         for track in smf.tracks.iter() {
-            let mut length = 0;
-            let mut saw_eot = false;
-            let mut vec = Vec::new();
-            writer.start_track_header(&mut vec);
-
-            for event in track.events.iter() {
-                length += SMFWriter::write_vtime(event.vtime as u64, &mut vec).unwrap(); // TODO: Handle error
-                writer.write_event(&mut vec, &(event.event), &mut length, &mut saw_eot);
-            }
+            writer.add_smf_track(track)?;
+        }
 
-            writer.finish_track_write(&mut vec, &mut length, saw_eot);
-            writer.tracks.push(vec);
+        Ok(writer)
+    }
+
+    /// Set whether tracks should be written using running status.
+    /// When enabled the status byte of a midi message is left out if
+    /// it's the same as the status of the previous message in the
+    /// track.  Meta and SysEx events interrupt the running status, so
+    /// the message after them will always have its status written.
+    /// This applies to all tracks, including those that were added
+    /// before this call.
+    pub fn set_running_status(&mut self, running_status: bool) {
+        if self.running_status != running_status {
+            self.running_status = running_status;
+            self.encode_tracks();
         }
+    }
 
-        writer
+    /// Set whether an EndOfTrack event should be added to the end of
+    /// tracks that don't contain one.  This is enabled by default.
+    /// When disabled, tracks are written with exactly the events they
+    /// contain.  This applies to all tracks, including those that were
+    /// added before this call.
+    pub fn set_auto_end_of_track(&mut self, auto_end_of_track: bool) {
+        if self.auto_end_of_track != auto_end_of_track {
+            self.auto_end_of_track = auto_end_of_track;
+            self.encode_tracks();
+        }
+    }
+
+    /// Get the number of tracks that have been added to this writer
+    pub fn num_tracks(&self) -> usize {
+        self.tracks.len()
+    }
+
+    /// Get the encoded bytes of the track at index `index`, as they
+    /// will be written out, including the `MTrk` chunk header.
+    /// Returns None if there is no such track, or if it was added by
+    /// `from_smf` and can't be written.
+    pub fn track_bytes(&self, index: usize) -> Option<&[u8]> {
+        self.encoded.get(index).and_then(|bytes| bytes.as_deref())
     }
 
     pub fn vtime_to_vec(val: u64) -> Vec<u8> {
-        let mut storage = Vec::new();
-        let mut cur = val;
-        let mut continuation = false;
-        let cont_mask = 0x80 as u8;
-        let val_mask = 0x7F as u64;
-        loop {
-            let mut to_write = (cur & val_mask) as u8;
-            cur = cur >> 7;
-            if continuation {
-                // we're writing a continuation byte, so set the bit
-                to_write |= cont_mask;
-            }
-            storage.push(to_write);
-            continuation = true;
-            if cur == 0 { break; }
-        }
-        storage.reverse();
-        storage
+        encode_vlq(val)
     }
 
-    // Write a variable length value.  Return number of bytes written.
+    /// Write a variable length value.  Return number of bytes written.
+    /// SMFs limit these values to four bytes, so values over
+    /// `MAX_VTIME` are rejected with an `InvalidInput` error.
     pub fn write_vtime(val: u64, writer: &mut dyn Write) -> Result<u32,Error> {
+        if val > MAX_VTIME {
+            return Err(Error::new(ErrorKind::InvalidInput, "Variable length value over 28 bits"));
+        }
         let storage = SMFWriter::vtime_to_vec(val);
         writer.write_all(&storage[..])?;
         Ok(storage.len() as u32)
@@ -113,33 +169,53 @@ impl SMFWriter {
         vec.push(0);
     }
 
-    fn write_event(&self, vec: &mut Vec<u8>, event: &Event, length: &mut u32, saw_eot: &mut bool) {
-        match event {
-            &Event::Midi(ref midi) => {
-                vec.extend(midi.data.iter());
-                *length += midi.data.len() as u32;
+    // Write an event.  `last_status` holds the status of the last
+    // message written, or 0 if there's no current running status
+    fn write_event(&self, vec: &mut Vec<u8>, event: &Event, length: &mut u32, saw_eot: &mut bool, last_status: &mut u8) -> Result<(),Error> {
+        match *event {
+            Event::Midi(ref midi) if midi.is_sysex() || midi.is_sysex_escape() => {
+                // SysEx events are stored with a length between the status and the data
+                vec.push(midi.data[0]);
+                *length += SMFWriter::write_vtime((midi.data.len()-1) as u64,vec)? + 1;
+                vec.extend(midi.data[1..].iter());
+                *length += (midi.data.len()-1) as u32;
+                *last_status = 0;
+            }
+            Event::Midi(ref midi) => {
+                let status = midi.raw_status();
+                if self.running_status && status == *last_status {
+                    vec.extend(midi.data[1..].iter());
+                    *length += (midi.data.len()-1) as u32;
+                } else {
+                    vec.extend(midi.data.iter());
+                    *length += midi.data.len() as u32;
+                }
+                // only channel messages can use running status
+                *last_status = if status < 0xF0 { status } else { 0 };
             }
-            &Event::Meta(ref meta) => {
+            Event::Meta(ref meta) => {
                 vec.push(0xff); // indicate we're writing a meta event
-                vec.push(meta.command as u8);
+                vec.push(meta.raw_command());
                 // +2 on next line for the 0xff and the command byte we just wrote
-                *length += SMFWriter::write_vtime(meta.length,vec).unwrap() + 2;
+                *length += SMFWriter::write_vtime(meta.length,vec)? + 2;
                 vec.extend(meta.data.iter());
                 *length += meta.data.len() as u32;
                 if meta.command == MetaCommand::EndOfTrack {
                     *saw_eot = true;
                 }
+                *last_status = 0;
             }
         }
+        Ok(())
     }
 
-    fn finish_track_write(&self, vec: &mut Vec<u8>, length: &mut u32, saw_eot: bool) {
-        if !saw_eot {
+    fn finish_track_write(&self, vec: &mut Vec<u8>, length: &mut u32, saw_eot: bool) -> Result<(),Error> {
+        if !saw_eot && self.auto_end_of_track {
             // no end of track marker in passed data, add one
-            *length += SMFWriter::write_vtime(0,vec).unwrap();
+            *length += SMFWriter::write_vtime(0,vec)?;
             vec.push(0xff); // indicate we're writing a meta event
             vec.push(MetaCommand::EndOfTrack as u8);
-            *length += SMFWriter::write_vtime(0,vec).unwrap() + 2; // write length of meta command: 0
+            *length += SMFWriter::write_vtime(0,vec)? + 2; // write length of meta command: 0
         }
 
         // write in the length in the space we reserved
@@ -149,43 +225,79 @@ impl SMFWriter {
             vec[7-i] = lbyte;
             *length = (*length)>>8;
         }
+        Ok(())
     }
 
-    /// Add any sequence of AbsoluteEvents as a track to this writer
-    pub fn add_track<'a,I>(&mut self, track: I) where I: Iterator<Item=&'a AbsoluteEvent> {
-        self.add_track_with_name(track,None)
+    // Encode the events of a track, including the chunk header
+    fn encode_track(&self, events: &[TrackEvent]) -> Result<Vec<u8>,Error> {
+        let mut length = 0;
+        let mut saw_eot = false;
+        let mut last_status = 0;
+        let mut vec = Vec::new();
+        self.start_track_header(&mut vec);
+
+        for event in events.iter() {
+            length += SMFWriter::write_vtime(event.vtime,&mut vec)?;
+            self.write_event(&mut vec, &(event.event), &mut length, &mut saw_eot, &mut last_status)?;
+        }
+
+        self.finish_track_write(&mut vec, &mut length, saw_eot)?;
+        Ok(vec)
     }
 
-    /// Add any sequence of AbsoluteEvents as a track to this writer.  A meta event with the given name will
-    /// be added at the start of the track
-    pub fn add_track_with_name<'a,I>(&mut self, track: I, name: Option<String>) where I: Iterator<Item=&'a AbsoluteEvent> {
-        let mut vec = Vec::new();
+    // Encode all the tracks again, after the encoding options changed
+    fn encode_tracks(&mut self) {
+        let encoded = self.tracks.iter().map(|track| self.encode_track(track).ok()).collect();
+        self.encoded = encoded;
+    }
 
-        self.start_track_header(&mut vec);
+    // Add a track if it can be encoded
+    fn push_track(&mut self, events: Vec<TrackEvent>) -> Result<(),Error> {
+        let bytes = self.encode_track(&events)?;
+        self.tracks.push(events);
+        self.encoded.push(Some(bytes));
+        Ok(())
+    }
 
-        let mut length = 0;
+    /// Add a track from an SMF to this writer.  Returns an
+    /// `InvalidInput` error, and doesn't add the track, if a delta
+    /// time or length in it is over `MAX_VTIME`.
+    pub fn add_smf_track(&mut self, track: &Track) -> Result<(),Error> {
+        self.push_track(track.events.clone())
+    }
+
+    /// Add any sequence of AbsoluteEvents as a track to this writer.
+    /// Fails like `add_smf_track`.
+    pub fn add_track<'a,I>(&mut self, track: I) -> Result<(),Error> where I: Iterator<Item=&'a AbsoluteEvent> {
+        self.add_track_with_name(track,None)
+    }
+
+    /// Add any sequence of AbsoluteEvents as a track to this writer.  A meta event with the given name will
+    /// be added at the start of the track.  Fails like `add_smf_track`.
+    pub fn add_track_with_name<'a,I>(&mut self, track: I, name: Option<String>) -> Result<(),Error> where I: Iterator<Item=&'a AbsoluteEvent> {
+        let mut events = Vec::new();
         let mut cur_time: u64 = 0;
-        let mut saw_eot = false;
 
         match name {
             Some(n) => {
-                let namemeta = Event::Meta(MetaEvent::sequence_or_track_name(n));
-                length += SMFWriter::write_vtime(0,&mut vec).unwrap();
-                self.write_event(&mut vec, &namemeta, &mut length, &mut saw_eot);
+                events.push(TrackEvent {
+                    vtime: 0,
+                    event: Event::Meta(MetaEvent::sequence_or_track_name(n)),
+                });
             }
             None => {}
         }
 
         for ev in track {
             let vtime = ev.get_time() - cur_time;
-            cur_time = vtime;
-            length += SMFWriter::write_vtime(vtime as u64,&mut vec).unwrap(); // TODO: Handle error
-            self.write_event(&mut vec, ev.get_event(), &mut length, &mut saw_eot);
+            cur_time = ev.get_time();
+            events.push(TrackEvent {
+                vtime,
+                event: ev.get_event().clone(),
+            });
         }
 
-        self.finish_track_write(&mut vec, &mut length, saw_eot);
-
-        self.tracks.push(vec);
+        self.push_track(events)
     }
 
     // actual writing stuff below
@@ -202,23 +314,59 @@ impl SMFWriter {
     /// Write out all the tracks that have been added to this
     /// SMFWriter to the passed writer
     pub fn write_all(self, writer: &mut dyn Write) -> Result<(),Error> {
+        self.write_all_ref(writer)
+    }
+
+    /// Write out all the tracks that have been added to this
+    /// SMFWriter to the passed writer, without consuming the
+    /// SMFWriter so it can be written again
+    pub fn write_all_ref(&self, writer: &mut dyn Write) -> Result<(),Error> {
         self.write_header(writer)?;
-        for track in self.tracks.into_iter() {
-            writer.write_all(&track[..])?;
+        for (track,bytes) in self.tracks.iter().zip(self.encoded.iter()) {
+            match *bytes {
+                Some(ref bytes) => writer.write_all(bytes)?,
+                // encoding it again gives the error it failed with
+                None => writer.write_all(&self.encode_track(track)?)?,
+            }
         }
         Ok(())
     }
 
+    /// Write out all the tracks that have been added to this
+    /// SMFWriter into a new in-memory buffer.  Fails like
+    /// `write_all_ref` if a track added by `from_smf` can't be
+    /// written.
+    pub fn to_vec(&self) -> Result<Vec<u8>,Error> {
+        let mut bytes = Vec::new();
+        self.write_all_ref(&mut bytes)?;
+        Ok(bytes)
+    }
+
     /// Write out the result of the tracks that have been added to a
     /// file.
     /// Warning: This will overwrite an existing file
     pub fn write_to_file(self, path: &Path) -> Result<(),Error> {
+        self.write_to_file_ref(path)
+    }
+
+    /// Write out the result of the tracks that have been added to a
+    /// file, without consuming the SMFWriter.
+    /// Warning: This will overwrite an existing file
+    pub fn write_to_file_ref(&self, path: &Path) -> Result<(),Error> {
         let mut file = OpenOptions::new().write(true).truncate(true).create(true).open(path)?;
-        self.write_all(&mut file)
+        self.write_all_ref(&mut file)
     }
 
 }
 
+impl From<SMF> for SMFWriter {
+    /// Create a writer that has all the tracks from the given SMF
+    /// already added, see `SMFWriter::from_smf`
+    fn from(smf: SMF) -> SMFWriter {
+        SMFWriter::from_smf(smf)
+    }
+}
+
 #[test]
 fn vwrite() {
     let mut vec1 = Vec::new();
@@ -235,5 +383,195 @@ fn vwrite() {
     assert!(vec1[0] == 0x82);
     assert!(vec1[1] == 0x80);
     assert!(vec1[2] == 0x00);
+
+    vec1.clear();
+    assert_eq!(SMFWriter::write_vtime(MAX_VTIME,&mut vec1).unwrap(),4);
+    assert_eq!(vec1,vec![0xFF,0xFF,0xFF,0x7F]);
+
+    vec1.clear();
+    assert_eq!(SMFWriter::write_vtime(MAX_VTIME+1,&mut vec1).unwrap_err().kind(),ErrorKind::InvalidInput);
+    assert!(vec1.is_empty());
 }
 
+
+#[test]
+fn running_status() {
+    use ::{MidiMessage,TrackEvent};
+
+    let events = vec![
+        (0, Event::Midi(MidiMessage::note_on(60,100,0))),
+        (0, Event::Midi(MidiMessage::note_on(64,100,0))),
+        (96, Event::Meta(MetaEvent::marker_text("A".to_string()))),
+        (0, Event::Midi(MidiMessage::note_on(60,0,0))),
+        (0, Event::Midi(MidiMessage::note_on(64,0,0))),
+    ];
+    let track = Track {
+        copyright: None,
+        name: None,
+        instrument: None,
+        events: events.into_iter().map(|(vtime,event)| TrackEvent { vtime, event }).collect(),
+    };
+
+    let mut writer = SMFWriter::new_with_division(96);
+    writer.set_running_status(true);
+    writer.add_smf_track(&track).unwrap();
+    let mut bytes = Vec::new();
+    writer.write_all(&mut bytes).unwrap();
+
+    let reference = vec![0x4D,0x54,0x68,0x64,0,0,0,6,0,1,0,1,0,0x60,
+                         0x4D,0x54,0x72,0x6B,0,0,0,23,
+                         0x00,0x90,0x3C,0x64,
+                         0x00,0x40,0x64,
+                         0x60,0xFF,0x06,0x01,0x41,
+                         0x00,0x90,0x3C,0x00,
+                         0x00,0x40,0x00,
+                         0x00,0xFF,0x2F,0x00];
+    assert_eq!(bytes,reference);
+
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    assert_eq!(smf.tracks[0].events.len(),6);
+    match smf.tracks[0].events[4].event {
+        Event::Midi(ref m) => assert_eq!(m.data,vec![0x90,64,0]),
+        _ => panic!("expected a note on"),
+    }
+}
+
+#[test]
+fn running_status_from_smf() {
+    use ::{MidiMessage,SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap().note_on_at(0,0,64,100,0).unwrap();
+    builder.add_midi_abs(0,96,MidiMessage::note_on(60,0,0)).unwrap();
+    let mut writer = SMFWriter::from_smf(builder.result());
+    let plain = writer.track_bytes(0).unwrap().to_vec();
+
+    // applies to the tracks that were added by from_smf
+    writer.set_running_status(true);
+    let running = writer.track_bytes(0).unwrap();
+    assert_eq!(&running[8..],&[0x00,0x90,0x3C,0x64,
+                               0x00,0x40,0x64,
+                               0x60,0x3C,0x00,
+                               0x00,0xFF,0x2F,0x00]);
+    assert_eq!(plain.len(),running.len() + 2);
+}
+
+#[test]
+fn vtime_out_of_range() {
+    use ::{AbsoluteEvent,MidiMessage,SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_midi_abs(0,MAX_VTIME+1,MidiMessage::note_on(60,100,0)).unwrap();
+    let smf = builder.result();
+
+    let mut writer = SMFWriter::new_with_division(96);
+    assert_eq!(writer.add_smf_track(&smf.tracks[0]).unwrap_err().kind(),ErrorKind::InvalidInput);
+    let events = [AbsoluteEvent::new_midi(MAX_VTIME+1,MidiMessage::note_on(60,100,0))];
+    assert_eq!(writer.add_track(events.iter()).unwrap_err().kind(),ErrorKind::InvalidInput);
+    assert_eq!(writer.num_tracks(),0);
+
+    assert_eq!(SMFWriter::try_from_smf(&smf).err().unwrap().kind(),ErrorKind::InvalidInput);
+    let writer = SMFWriter::from_smf(smf);
+    assert!(writer.track_bytes(0).is_none());
+    let mut bytes = Vec::new();
+    assert_eq!(writer.write_all(&mut bytes).unwrap_err().kind(),ErrorKind::InvalidInput);
+}
+
+#[test]
+fn add_track_deltas() {
+    use ::MidiMessage;
+
+    let events = [
+        AbsoluteEvent::new_midi(0,MidiMessage::note_on(60,100,0)),
+        AbsoluteEvent::new_midi(10,MidiMessage::note_on(62,100,0)),
+        AbsoluteEvent::new_midi(25,MidiMessage::note_on(64,100,0)),
+    ];
+    let mut writer = SMFWriter::new_with_division(96);
+    writer.add_track(events.iter()).unwrap();
+    let mut bytes = Vec::new();
+    writer.write_all(&mut bytes).unwrap();
+
+    let smf = SMF::from_reader(&mut &bytes[..]).unwrap();
+    let vtimes: Vec<u64> = smf.tracks[0].events.iter().map(|e| e.vtime).collect();
+    // the last event is the added EndOfTrack
+    assert_eq!(vtimes,vec![0,10,15,0]);
+}
+
+#[test]
+fn write_twice() {
+    use ::{SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap().note_off_at(0,96,60,0,0).unwrap();
+    let writer = SMFWriter::from_smf(builder.result());
+
+    let mut first = Vec::new();
+    let mut second = Vec::new();
+    writer.write_all_ref(&mut first).unwrap();
+    writer.write_all_ref(&mut second).unwrap();
+    assert!(!first.is_empty());
+    assert_eq!(first,second);
+
+    let mut consumed = Vec::new();
+    writer.write_all(&mut consumed).unwrap();
+    assert_eq!(first,consumed);
+}
+
+#[test]
+fn track_bytes() {
+    use ::{SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.add_track();
+    builder.note_on_at(1,0,60,100,0).unwrap().note_off_at(1,96,60,0,0).unwrap();
+    let writer = SMFWriter::from_smf(builder.result());
+    assert_eq!(writer.num_tracks(),2);
+    assert!(writer.track_bytes(2).is_none());
+
+    // empty track only gets an EndOfTrack
+    assert_eq!(writer.track_bytes(0).unwrap(),&[0x4D,0x54,0x72,0x6B,0,0,0,4,0x00,0xFF,0x2F,0x00][..]);
+
+    let bytes = writer.track_bytes(1).unwrap();
+    assert_eq!(&bytes[..4],b"MTrk");
+    let length = ((bytes[4] as usize) << 24) | ((bytes[5] as usize) << 16) | ((bytes[6] as usize) << 8) | bytes[7] as usize;
+    assert_eq!(length,bytes.len() - 8);
+    assert_eq!(length,12);
+}
+
+#[test]
+fn auto_end_of_track() {
+    use ::{SMFBuilder};
+
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap().note_off_at(0,96,60,0,0).unwrap();
+    let smf = builder.result();
+
+    let mut with_eot = SMFWriter::new_with_division(96);
+    with_eot.add_smf_track(&smf.tracks[0]).unwrap();
+    let mut without_eot = SMFWriter::new_with_division(96);
+    without_eot.set_auto_end_of_track(false);
+    without_eot.add_smf_track(&smf.tracks[0]).unwrap();
+
+    let with_eot = with_eot.track_bytes(0).unwrap();
+    let without_eot = without_eot.track_bytes(0).unwrap();
+    assert_eq!(&with_eot[7..8],&[12]);
+    assert_eq!(&without_eot[7..8],&[8]);
+    assert_eq!(&with_eot[8..with_eot.len()-4],&without_eot[8..]);
+    assert_eq!(&with_eot[with_eot.len()-4..],&[0x00,0xFF,0x2F,0x00]);
+
+    // an EndOfTrack in the track is written as is either way
+    let mut builder = SMFBuilder::new();
+    builder.add_track();
+    builder.note_on_at(0,0,60,100,0).unwrap().meta_at(0,10,MetaEvent::end_of_track()).unwrap();
+    let smf = builder.result();
+    let mut writer = SMFWriter::new_with_division(96);
+    writer.add_smf_track(&smf.tracks[0]).unwrap();
+    writer.set_auto_end_of_track(false);
+    writer.add_smf_track(&smf.tracks[0]).unwrap();
+    assert_eq!(writer.track_bytes(0).unwrap(),writer.track_bytes(1).unwrap());
+}